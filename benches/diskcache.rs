@@ -12,7 +12,12 @@ const TOTAL_SIZE: usize = BLOCK_SIZE * BLOCK_NUM;
 
 async fn bench_diskcache() {
     let tempdir = tempfile::tempdir().unwrap();
-    let cache = DiskCache::open(tempdir).await.unwrap();
+    let cache = DiskCache::builder(&tempdir)
+        .capacity(2 * TOTAL_SIZE)
+        .block_size(BLOCK_SIZE)
+        .open()
+        .await
+        .unwrap();
 
     let mut block_ids = Vec::new();
     for i in 0..BLOCK_NUM {
@@ -1,5 +1,13 @@
+use common_cache::admission::{Admitted, TinyLfu};
+use common_cache::arc::ArcCache;
+use common_cache::clock::{Clock, ClockPro};
 use common_cache::fifo::Fifo;
+use common_cache::lfu::Lfu;
+use common_cache::s3fifo::S3Fifo;
+use common_cache::sieve::Sieve;
+use common_cache::wtinylfu::WTinyLfu;
 use common_cache::BasicCache;
+use common_cache::Count;
 use criterion::{criterion_group, criterion_main, Criterion};
 use hashlink::LruCache;
 use std::sync::Arc;
@@ -60,7 +68,48 @@ impl<C: BasicCache<KeyType, ValueType>> TestCache<C> {
 }
 
 fn fifo_bench(commands: Arc<Vec<Command>>) {
-    let mut test_cache = TestCache::new(Fifo::new(CACHE_SIZE), commands);
+    let mut test_cache = TestCache::new(Fifo::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn s3fifo_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(S3Fifo::with_capacity(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn sieve_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(Sieve::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn lfu_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(Lfu::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn arc_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(ArcCache::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn wtinylfu_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(WTinyLfu::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn tinylfu_fifo_bench(commands: Arc<Vec<Command>>) {
+    let cache = Admitted::new(Fifo::new(CACHE_SIZE as u64), Count, TinyLfu::new(CACHE_SIZE));
+    let mut test_cache = TestCache::new(cache, commands);
+    test_cache.run();
+}
+
+fn clock_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(Clock::new(CACHE_SIZE as u64), commands);
+    test_cache.run();
+}
+
+fn clockpro_bench(commands: Arc<Vec<Command>>) {
+    let mut test_cache = TestCache::new(ClockPro::new(CACHE_SIZE as u64), commands);
     test_cache.run();
 }
 
@@ -73,6 +122,22 @@ fn cache_bench(c: &mut Criterion) {
     let commands = Arc::new(generate_bench_commands());
     c.bench_function("lru_bench", |b| b.iter(|| lru_bench(commands.clone())));
     c.bench_function("fifo_bench", |b| b.iter(|| fifo_bench(commands.clone())));
+    c.bench_function("s3fifo_bench", |b| {
+        b.iter(|| s3fifo_bench(commands.clone()))
+    });
+    c.bench_function("sieve_bench", |b| b.iter(|| sieve_bench(commands.clone())));
+    c.bench_function("lfu_bench", |b| b.iter(|| lfu_bench(commands.clone())));
+    c.bench_function("arc_bench", |b| b.iter(|| arc_bench(commands.clone())));
+    c.bench_function("wtinylfu_bench", |b| {
+        b.iter(|| wtinylfu_bench(commands.clone()))
+    });
+    c.bench_function("tinylfu_fifo_bench", |b| {
+        b.iter(|| tinylfu_fifo_bench(commands.clone()))
+    });
+    c.bench_function("clock_bench", |b| b.iter(|| clock_bench(commands.clone())));
+    c.bench_function("clockpro_bench", |b| {
+        b.iter(|| clockpro_bench(commands.clone()))
+    });
 }
 
 criterion_group!(benches, cache_bench);
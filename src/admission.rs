@@ -0,0 +1,267 @@
+mod sketch;
+
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+
+use self::sketch::CountMinSketch;
+use self::sketch::Doorkeeper;
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+/// Number of items a default `TinyLfu` is sized for.
+const DEFAULT_TINY_LFU_CAPACITY: usize = 64 * 1024;
+
+/// Largest number of items a filter built by a cache is sized for.
+pub(crate) const MAX_SKETCH_CAPACITY: u64 = 1024 * 1024;
+
+/// Records per item of the capacity between two halvings of a `TinyLfu`.
+const SAMPLE_FACTOR: usize = 10;
+
+/// Decides whether a new item is worth evicting another one for, from the hashes of their keys.
+///
+/// Working on hashes keeps it object safe, and lets a cache look keys up by any `Borrow` form
+/// since they hash the same.
+pub trait Admission {
+    /// Creates a filter sized for a cache of about `capacity` items.
+    fn with_capacity(capacity: usize) -> Self
+    where
+        Self: Sized;
+
+    /// Records an access to the key with the given hash, cached or not.
+    fn record(&mut self, hash: u64);
+
+    /// Checks if the item with the `candidate` hash should be cached in place of the one with the
+    /// `victim` hash.
+    fn admit(&self, candidate: u64, victim: u64) -> bool;
+}
+
+/// The TinyLFU admission filter, see <https://arxiv.org/abs/1512.00727>.
+///
+/// The accesses are counted by a Count-Min sketch of 4-bit counters, behind a doorkeeper Bloom
+/// filter so the keys seen once don't take room in the sketch. A candidate is admitted only if
+/// it was accessed more often than the victim. After 10 records per item of the capacity, every
+/// counter is halved and the doorkeeper cleared, so the filter follows a changing workload.
+pub struct TinyLfu {
+    sketch: CountMinSketch,
+    doorkeeper: Doorkeeper,
+    /// Records since the last halving.
+    records: usize,
+    sample_size: usize,
+}
+
+impl TinyLfu {
+    /// Creates a filter sized for a cache of about `capacity` items.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let sample_size = capacity.saturating_mul(SAMPLE_FACTOR);
+        Self {
+            sketch: CountMinSketch::new(capacity),
+            doorkeeper: Doorkeeper::new(sample_size),
+            records: 0,
+            sample_size,
+        }
+    }
+
+    /// Returns the estimated number of recent accesses to the key with the given hash, at most
+    /// 16.
+    pub fn estimate(&self, hash: u64) -> u64 {
+        self.sketch.estimate(hash) + u64::from(self.doorkeeper.contains(hash))
+    }
+
+    /// Forgets every access.
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+        self.doorkeeper.clear();
+        self.records = 0;
+    }
+}
+
+/// A filter sized for a cache of 65536 items.
+impl Default for TinyLfu {
+    fn default() -> Self {
+        Self::new(DEFAULT_TINY_LFU_CAPACITY)
+    }
+}
+
+impl Admission for TinyLfu {
+    fn with_capacity(capacity: usize) -> Self {
+        Self::new(capacity)
+    }
+
+    fn record(&mut self, hash: u64) {
+        if !self.doorkeeper.insert(hash) {
+            self.sketch.increment(hash);
+        }
+        self.records += 1;
+        if self.records >= self.sample_size {
+            self.sketch.halve();
+            self.doorkeeper.clear();
+            self.records = 0;
+        }
+    }
+
+    /// On a tie the victim stays, so a new key has to prove itself first.
+    fn admit(&self, candidate: u64, victim: u64) -> bool {
+        self.estimate(candidate) > self.estimate(victim)
+    }
+}
+
+/// A `Cache` policy behind an `Admission` filter.
+///
+/// Every access is recorded by the filter, and a new item that would evict another one is only
+/// cached if the filter prefers it to the item the policy would evict first. An update of a
+/// cached key is always let in.
+///
+/// The meter must measure the items like the one of the cache, since it decides when an item
+/// would evict another one.
+pub struct Admitted<C, S = DefaultHashBuilder, M = Count, A = TinyLfu> {
+    cache: C,
+    admission: A,
+    hash_builder: S,
+    meter: M,
+}
+
+impl<C, M, A> Admitted<C, DefaultHashBuilder, M, A> {
+    /// Puts `cache`, bounded as measured by `meter`, behind the `admission` filter.
+    pub fn new(cache: C, meter: M, admission: A) -> Self {
+        Self {
+            cache,
+            admission,
+            hash_builder: DefaultHashBuilder::default(),
+            meter,
+        }
+    }
+}
+
+impl<C, S, M, A> Admitted<C, S, M, A> {
+    /// Returns the cache behind the filter.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Returns the admission filter.
+    pub fn admission(&self) -> &A {
+        &self.admission
+    }
+}
+
+impl<K, V, C, S, M, A> Cache<K, V, S, M> for Admitted<C, S, M, A>
+where
+    K: Hash + Eq,
+    C: Cache<K, V, S, M>,
+    S: BuildHasher + Clone,
+    M: CountableMeter<K, V> + Clone,
+    A: Admission,
+{
+    /// The filter is sized for as many items as the capacity, up to a million.
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            cache: C::with_meter_and_hasher(capacity, meter.clone(), hash_builder.clone()),
+            admission: A::with_capacity(Ord::min(capacity, MAX_SKETCH_CAPACITY) as usize),
+            hash_builder,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.admission.record(self.hash_builder.hash_one(k));
+        self.cache.get(k)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.peek(k)
+    }
+
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.cache.peek_by_policy()
+    }
+
+    /// Returns `None` without caching the item if the filter rejects it.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let hash = self.hash_builder.hash_one(&k);
+        self.admission.record(hash);
+        let new_size = self.meter.size(self.meter.measure(&k, &v)).unwrap_or(1);
+        if !self.cache.contains(&k) && self.cache.size() + new_size > self.cache.capacity() {
+            if let Some((victim, _)) = self.cache.peek_by_policy() {
+                let victim = self.hash_builder.hash_one(victim);
+                if !self.admission.admit(hash, victim) {
+                    return None;
+                }
+            }
+        }
+        self.cache.put(k, v)
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.pop(k)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        self.cache.pop_by_policy()
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.contains(k)
+    }
+
+    fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.cache.capacity()
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        self.cache.set_capacity(capacity);
+    }
+
+    fn size(&self) -> u64 {
+        self.cache.size()
+    }
+
+    /// The accesses recorded by the filter are kept.
+    fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+impl<K, V, C, A> BasicCache<K, V> for Admitted<C, DefaultHashBuilder, Count, A>
+where
+    K: Hash + Eq,
+    C: Cache<K, V, DefaultHashBuilder, Count>,
+    A: Admission,
+{
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
@@ -0,0 +1,167 @@
+/// Number of rows of the sketch, each indexed by its own hash of the key.
+const DEPTH: usize = 4;
+
+/// Counters per `u64` word, every counter takes 4 bits.
+const COUNTERS_PER_WORD: usize = 16;
+
+/// Largest value of a counter.
+const MAX_COUNT: u64 = 15;
+
+/// Seeds deriving the hash of every row from the hash of the key.
+const SEEDS: [u64; DEPTH] = [
+    0xc3a5_c85c_97cb_3127,
+    0xb492_b66f_be98_f273,
+    0x9ae1_6a3b_2f90_404f,
+    0xcbf2_9ce4_8422_2325,
+];
+
+/// Spreads `hash` mixed with `seed` over 64 bits.
+fn mix(hash: u64, seed: u64) -> u64 {
+    let hash = (hash ^ seed).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    hash ^ (hash >> 32)
+}
+
+/// A Count-Min sketch of 4-bit counters estimating how often a key was recorded.
+///
+/// The estimate of a key is the smallest of its counters, so it's never lower than the real
+/// count up to 15, and higher only when every row collides with other keys.
+pub(super) struct CountMinSketch {
+    table: Vec<u64>,
+    /// Counters per row minus one, a power of two minus one.
+    mask: usize,
+}
+
+impl CountMinSketch {
+    /// Creates a sketch with at least `width` counters per row.
+    pub(super) fn new(width: usize) -> Self {
+        let width = width.max(COUNTERS_PER_WORD).next_power_of_two();
+        Self {
+            table: vec![0; DEPTH * width / COUNTERS_PER_WORD],
+            mask: width - 1,
+        }
+    }
+
+    /// Returns the word and the shift of the counter of `hash` in `row`.
+    fn counter(&self, hash: u64, row: usize) -> (usize, usize) {
+        let index = row * (self.mask + 1) + (mix(hash, SEEDS[row]) as usize & self.mask);
+        (index / COUNTERS_PER_WORD, (index % COUNTERS_PER_WORD) * 4)
+    }
+
+    /// Counts one more occurrence of `hash`.
+    pub(super) fn increment(&mut self, hash: u64) {
+        for row in 0..DEPTH {
+            let (word, shift) = self.counter(hash, row);
+            if (self.table[word] >> shift) & MAX_COUNT < MAX_COUNT {
+                self.table[word] += 1 << shift;
+            }
+        }
+    }
+
+    /// Returns the estimated count of `hash`, at most 15.
+    pub(super) fn estimate(&self, hash: u64) -> u64 {
+        (0..DEPTH)
+            .map(|row| {
+                let (word, shift) = self.counter(hash, row);
+                (self.table[word] >> shift) & MAX_COUNT
+            })
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Halves every counter, so old occurrences weigh less than new ones.
+    pub(super) fn halve(&mut self) {
+        for word in &mut self.table {
+            *word = (*word >> 1) & 0x7777_7777_7777_7777;
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.table.fill(0);
+    }
+}
+
+/// Number of bits set per key in the doorkeeper.
+const DOORKEEPER_PROBES: u64 = 3;
+
+/// A Bloom filter recording the keys seen once, so keys seen only once never reach the sketch.
+pub(super) struct Doorkeeper {
+    bits: Vec<u64>,
+    /// Bits minus one, a power of two minus one.
+    mask: u64,
+}
+
+impl Doorkeeper {
+    /// Creates a filter of at least `bits` bits.
+    pub(super) fn new(bits: usize) -> Self {
+        let bits = bits.max(64).next_power_of_two();
+        Self {
+            bits: vec![0; bits / 64],
+            mask: bits as u64 - 1,
+        }
+    }
+
+    /// Returns the word and the bit of the `probe`th bit of `hash`, double hashing the two
+    /// halves of it.
+    fn bit(&self, hash: u64, probe: u64) -> (usize, u64) {
+        let (low, high) = (hash, (hash >> 32) | 1);
+        let bit = low.wrapping_add(probe.wrapping_mul(high)) & self.mask;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    }
+
+    /// Checks if `hash` may have been inserted.
+    pub(super) fn contains(&self, hash: u64) -> bool {
+        (0..DOORKEEPER_PROBES).all(|probe| {
+            let (word, bit) = self.bit(hash, probe);
+            self.bits[word] & bit != 0
+        })
+    }
+
+    /// Inserts `hash`, returns `false` if it may have been inserted already.
+    pub(super) fn insert(&mut self, hash: u64) -> bool {
+        let mut inserted = false;
+        for probe in 0..DOORKEEPER_PROBES {
+            let (word, bit) = self.bit(hash, probe);
+            inserted |= self.bits[word] & bit == 0;
+            self.bits[word] |= bit;
+        }
+        inserted
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch() {
+        let mut sketch = CountMinSketch::new(1024);
+        for hash in 0..100 {
+            for _ in 0..hash % 20 {
+                sketch.increment(hash);
+            }
+        }
+        for hash in 0..100 {
+            assert!(sketch.estimate(hash) >= Ord::min(hash % 20, MAX_COUNT));
+        }
+        assert_eq!(sketch.estimate(7), 7);
+        sketch.halve();
+        assert_eq!(sketch.estimate(7), 3);
+        sketch.clear();
+        assert_eq!(sketch.estimate(7), 0);
+    }
+
+    #[test]
+    fn test_doorkeeper() {
+        let mut doorkeeper = Doorkeeper::new(1024);
+        assert!(!doorkeeper.contains(42));
+        assert!(doorkeeper.insert(42));
+        assert!(doorkeeper.contains(42));
+        assert!(!doorkeeper.insert(42));
+        doorkeeper.clear();
+        assert!(!doorkeeper.contains(42));
+    }
+}
@@ -0,0 +1,387 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+/// Hashes of evicted keys and the size of their items, the oldest at the front.
+type Ghost<S> = LinkedHashMap<u64, u64, S>;
+
+/// Remembers an evicted key in a ghost list.
+fn remember<S: BuildHasher>(ghost: &mut Ghost<S>, ghost_size: &mut u64, hash: u64, size: u64) {
+    if let Some(old_size) = ghost.insert(hash, size) {
+        *ghost_size -= old_size;
+    }
+    *ghost_size += size;
+}
+
+/// An ARC cache, see "ARC: A Self-Tuning, Low Overhead Replacement Cache" (FAST '03).
+///
+/// Items seen once recently are kept in `t1` and items seen at least twice in `t2`, both in LRU
+/// order. The keys they evict are remembered in the `b1` and `b2` ghost lists. A hit in `b1`
+/// means `t1` was too small and grows its target size `p`, a hit in `b2` shrinks it, so the
+/// cache adapts between recency, e.g. scans, and frequency, e.g. a hot set.
+///
+/// The capacity is measured by the `Meter`, and so are `p` and the ghost lists: `t1` and `b1`
+/// together hold at most the capacity, all four lists at most twice the capacity.
+pub struct ArcCache<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// The least recently used item is at the front.
+    t1: LinkedHashMap<K, V, S>,
+    /// The least recently used item is at the front.
+    t2: LinkedHashMap<K, V, S>,
+    /// Keys evicted from `t1`.
+    b1: Ghost<S>,
+    /// Keys evicted from `t2`.
+    b2: Ghost<S>,
+    t1_measure: M::Measure,
+    t2_measure: M::Measure,
+    b1_size: u64,
+    b2_size: u64,
+    /// Target size of `t1`.
+    p: u64,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> ArcCache<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> ArcCache<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, M: CountableMeter<K, V>> ArcCache<K, V, S, M> {
+    /// Returns the target size of the items seen once recently, `p` in the paper.
+    pub fn target(&self) -> u64 {
+        self.p
+    }
+
+    /// Returns the size of `measure`, or `count` if the meter doesn't measure anything.
+    fn size_of(&self, measure: M::Measure, count: usize) -> u64 {
+        self.meter.size(measure).unwrap_or(count as u64)
+    }
+
+    fn t1_size(&self) -> u64 {
+        self.size_of(self.t1_measure, self.t1.len())
+    }
+
+    fn hash_of<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+    {
+        self.b1.hasher().hash_one(key)
+    }
+
+    /// Checks if the next eviction picks from `t1`. Making room for a `b2` hit evicts from `t1`
+    /// when it's right at its target too.
+    fn evicts_t1(&self, b2_hit: bool) -> bool {
+        let t1_size = self.t1_size();
+        !self.t1.is_empty()
+            && (t1_size > self.p || (b2_hit && t1_size == self.p) || self.t2.is_empty())
+    }
+
+    /// Evicts the least recently used item of `t1` or `t2` and remembers its key.
+    fn evict(&mut self, b2_hit: bool) -> Option<(K, V)> {
+        let (key, value) = if self.evicts_t1(b2_hit) {
+            let (key, value) = self.t1.pop_front()?;
+            let measure = self.meter.measure(&key, &value);
+            self.t1_measure = self.meter.sub(self.t1_measure, measure);
+            let (hash, size) = (self.hash_of(&key), self.size_of(measure, 1));
+            remember(&mut self.b1, &mut self.b1_size, hash, size);
+            (key, value)
+        } else {
+            let (key, value) = self.t2.pop_front()?;
+            let measure = self.meter.measure(&key, &value);
+            self.t2_measure = self.meter.sub(self.t2_measure, measure);
+            let (hash, size) = (self.hash_of(&key), self.size_of(measure, 1));
+            remember(&mut self.b2, &mut self.b2_size, hash, size);
+            (key, value)
+        };
+        self.trim_ghosts();
+        Some((key, value))
+    }
+
+    /// Forgets the oldest ghosts until `t1` and `b1` fit the capacity and all the lists twice
+    /// the capacity.
+    fn trim_ghosts(&mut self) {
+        while self.t1_size().saturating_add(self.b1_size) > self.max_capacity {
+            match self.b1.pop_front() {
+                Some((_, size)) => self.b1_size -= size,
+                None => break,
+            }
+        }
+        let ghosts_size = |cache: &Self| cache.b1_size.saturating_add(cache.b2_size);
+        while self.size().saturating_add(ghosts_size(self)) > self.max_capacity.saturating_mul(2) {
+            if let Some((_, size)) = self.b2.pop_front() {
+                self.b2_size -= size;
+            } else if let Some((_, size)) = self.b1.pop_front() {
+                self.b1_size -= size;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Replaces the value of a cached key, counting it as a hit, and returns the old value.
+    fn update(&mut self, k: K, v: V, new_measure: M::Measure) -> Option<V> {
+        let old = if let Some((key, old)) = self.t1.remove_entry(&k) {
+            let old_measure = self.meter.measure(&key, &old);
+            self.t1_measure = self.meter.sub(self.t1_measure, old_measure);
+            old
+        } else {
+            let old = self.t2.remove(&k)?;
+            let old_measure = self.meter.measure(&k, &old);
+            self.t2_measure = self.meter.sub(self.t2_measure, old_measure);
+            old
+        };
+        self.t2_measure = self.meter.add(self.t2_measure, new_measure);
+        self.t2.insert(k, v);
+        while self.size() > self.capacity() {
+            self.evict(false);
+        }
+        self.trim_ghosts();
+        Some(old)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for ArcCache<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            t1: LinkedHashMap::with_hasher(hash_builder.clone()),
+            t2: LinkedHashMap::with_hasher(hash_builder.clone()),
+            b1: LinkedHashMap::with_hasher(hash_builder.clone()),
+            b2: LinkedHashMap::with_hasher(hash_builder),
+            t1_measure: Default::default(),
+            t2_measure: Default::default(),
+            b1_size: 0,
+            b2_size: 0,
+            p: 0,
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    /// A hit in `t1` promotes the item to `t2`.
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some((key, value)) = self.t1.remove_entry(k) {
+            let measure = self.meter.measure(k, &value);
+            self.t1_measure = self.meter.sub(self.t1_measure, measure);
+            self.t2_measure = self.meter.add(self.t2_measure, measure);
+            self.t2.insert(key, value);
+            return self.t2.back().map(|(_, value)| value);
+        }
+        self.t2.to_back(k).map(|value| &*value)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.t1.get(k).or_else(|| self.t2.get(k))
+    }
+
+    /// The least recently used item of `t1` if it's over its target, of `t2` otherwise.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        if self.evicts_t1(false) {
+            self.t1.front()
+        } else {
+            self.t2.front()
+        }
+    }
+
+    /// Updating an existing key counts as a hit.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        let new_size = self.size_of(new_measure, 1);
+        if new_size > self.max_capacity {
+            // It could never fit, so neither list is flushed for it. The value it replaces is
+            // dropped without leaving a ghost, like any other removal.
+            return self.pop(&k);
+        }
+        if self.contains(&k) {
+            return self.update(k, v, new_measure);
+        }
+
+        // A ghost hit means the list that evicted the key should have been larger.
+        let hash = self.hash_of(&k);
+        let b1_hit = self.b1.contains_key(&hash);
+        let b2_hit = !b1_hit && self.b2.contains_key(&hash);
+        if b1_hit {
+            // Saturate, the sizes of a `Meter` may be anywhere in the `u64` range.
+            let ratio = Ord::max(self.b2_size / Ord::max(self.b1_size, 1), 1);
+            let delta = new_size.saturating_mul(ratio);
+            self.p = Ord::min(self.p.saturating_add(delta), self.max_capacity);
+            self.b1_size -= self.b1.remove(&hash).unwrap_or_default();
+        } else if b2_hit {
+            let ratio = Ord::max(self.b1_size / Ord::max(self.b2_size, 1), 1);
+            let delta = new_size.saturating_mul(ratio);
+            self.p = self.p.saturating_sub(delta);
+            self.b2_size -= self.b2.remove(&hash).unwrap_or_default();
+        }
+
+        while self.size().saturating_add(new_size) > self.max_capacity {
+            if self.evict(b2_hit).is_none() {
+                break;
+            }
+        }
+        if b1_hit || b2_hit {
+            self.t2_measure = self.meter.add(self.t2_measure, new_measure);
+            self.t2.insert(k, v);
+        } else {
+            self.t1_measure = self.meter.add(self.t1_measure, new_measure);
+            self.t1.insert(k, v);
+        }
+        self.trim_ghosts();
+        None
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(value) = self.t1.remove(k) {
+            let measure = self.meter.measure(k, &value);
+            self.t1_measure = self.meter.sub(self.t1_measure, measure);
+            return Some(value);
+        }
+        let value = self.t2.remove(k)?;
+        let measure = self.meter.measure(k, &value);
+        self.t2_measure = self.meter.sub(self.t2_measure, measure);
+        Some(value)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        self.evict(false)
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.t1.contains_key(k) || self.t2.contains_key(k)
+    }
+
+    fn len(&self) -> usize {
+        self.t1.len() + self.t2.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.t1.is_empty() && self.t2.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        self.max_capacity = capacity;
+        self.p = Ord::min(self.p, capacity);
+        while self.size() > capacity {
+            self.evict(false);
+        }
+        self.trim_ghosts();
+    }
+
+    fn size(&self) -> u64 {
+        let measure = self.meter.add(self.t1_measure, self.t2_measure);
+        self.size_of(measure, self.len())
+    }
+
+    fn clear(&mut self) {
+        self.t1.clear();
+        self.t2.clear();
+        self.b1.clear();
+        self.b2.clear();
+        self.t1_measure = Default::default();
+        self.t2_measure = Default::default();
+        self.b1_size = 0;
+        self.b2_size = 0;
+        self.p = 0;
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for ArcCache<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::BytesMeter;
+    use crate::FileSize;
+
+    #[test]
+    fn test_bounded_ghosts() {
+        let seed = rand::random();
+        // Only shown if the test fails, to replay the same operations.
+        println!("seed: {seed}");
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut cache = ArcCache::with_meter(100, BytesMeter);
+        for _ in 0..10_000 {
+            let key = rng.gen_range(0..50_u8);
+            if rng.gen() {
+                cache.get(&key);
+            } else {
+                cache.put(key, vec![0u8; rng.gen_range(1..=20)]);
+            }
+            assert!(cache.size() <= 100);
+            assert!(cache.target() <= 100);
+            assert_eq!(cache.b1_size, cache.b1.values().sum());
+            assert_eq!(cache.b2_size, cache.b2.values().sum());
+            assert!(cache.t1_size() + cache.b1_size <= 100);
+            assert!(cache.size() + cache.b1_size + cache.b2_size <= 200);
+        }
+    }
+
+    /// Test that the target adapts without overflowing when the ghost sizes are huge.
+    #[test]
+    fn test_huge_sizes() {
+        let quarter = u64::MAX / 4;
+        let mut cache = ArcCache::with_meter(u64::MAX / 2, FileSize);
+        cache.put(10, quarter);
+        cache.get(&10);
+        cache.put(11, quarter);
+        cache.get(&11);
+        // Making room for `12` moves `1` to `b1`, then `10` to `b2`.
+        cache.put(1, 1);
+        cache.put(12, quarter);
+        assert_eq!(cache.b1_size, 1);
+        assert_eq!(cache.b2_size, quarter);
+        // The `b1` hit grows the target by a quarter of the `u64` range, times 4.
+        cache.put(1, 4);
+        assert_eq!(cache.target(), u64::MAX / 2);
+        assert!(cache.contains(&1));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2021 Datafuse Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::linked_hash_map;
+use hashlink::LinkedHashMap;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+
+/// An LRU cache.
+pub struct LruCache<
+    K: Eq + Hash,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    M: CountableMeter<K, V> = Count,
+> {
+    map: LinkedHashMap<K, V, S>,
+    current_measure: M::Measure,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Eq + Hash, V> LruCache<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        LruCache {
+            map: LinkedHashMap::new(),
+            current_measure: (),
+            max_capacity: capacity,
+            meter: Count,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, M: CountableMeter<K, V>> LruCache<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> LruCache<K, V, DefaultHashBuilder, M> {
+        LruCache {
+            map: LinkedHashMap::new(),
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> LruCache<K, V, S, Count> {
+    /// Creates an empty cache that can hold at most `capacity` items with the given hash builder.
+    pub fn with_hasher(capacity: u64, hash_builder: S) -> LruCache<K, V, S, Count> {
+        LruCache {
+            map: LinkedHashMap::with_hasher(hash_builder),
+            current_measure: (),
+            max_capacity: capacity,
+            meter: Count,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for LruCache<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        LruCache {
+            map: LinkedHashMap::with_hasher(hash_builder),
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.map.raw_entry_mut().from_key(k) {
+            linked_hash_map::RawEntryMut::Occupied(mut occupied) => {
+                occupied.to_back();
+                Some(occupied.into_mut())
+            }
+            linked_hash_map::RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(k)
+    }
+
+    /// The least recently used item is at the front of the map.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.map.front()
+    }
+
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_size = self.meter.measure(&k, &v);
+        if self.meter.size(new_size).unwrap_or(1) > self.max_capacity {
+            // It could never fit, so nothing is evicted for it. The value it replaces is dropped.
+            return self.pop(&k);
+        }
+        self.current_measure = self.meter.add(self.current_measure, new_size);
+        if let Some(old) = self.map.get(&k) {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, old));
+        }
+        let old_val = self.map.insert(k, v);
+        while self.size() > self.capacity() {
+            self.pop_by_policy();
+        }
+        old_val
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(k).inspect(|v| {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(k, v));
+        })
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        self.map.pop_front().map(|(k, v)| {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, &v));
+            (k, v)
+        })
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.max_capacity = capacity;
+    }
+
+    fn size(&self) -> u64 {
+        self.meter
+            .size(self.current_measure)
+            .unwrap_or_else(|| self.map.len() as u64)
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.current_measure = Default::default();
+    }
+}
@@ -0,0 +1,218 @@
+mod pro;
+
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+pub use self::pro::ClockPro;
+
+struct Item<V> {
+    referenced: AtomicBool,
+    value: V,
+}
+
+impl<V> Item<V> {
+    fn new(value: V) -> Self {
+        Self {
+            referenced: AtomicBool::new(false),
+            value,
+        }
+    }
+}
+
+/// A CLOCK cache: a FIFO queue giving a second chance to the items hit since the hand last
+/// passed them.
+///
+/// The queue is the ring and its front is the hand. An evicted item with its reference bit set
+/// gets it cleared and goes to the back instead, so hits are O(1) and evictions amortized O(1).
+/// A hit only sets the atomic reference bit, so it doesn't need exclusive access.
+pub struct Clock<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// The item under the hand is at the front, new items are inserted at the back.
+    ring: LinkedHashMap<K, Item<V>, S>,
+    current_measure: M::Measure,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> Clock<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> Clock<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Clock<K, V, S, M> {
+    /// Returns a reference to the value of the given key and records the hit.
+    ///
+    /// Only the atomic reference bit of the item is set, so this doesn't need exclusive access.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let item = self.ring.get(k)?;
+        item.referenced.store(true, SeqCst);
+        Some(&item.value)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for Clock<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            ring: LinkedHashMap::with_hasher(hash_builder),
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Clock::get(self, k)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.ring.get(k).map(|item| &item.value)
+    }
+
+    /// The first item past the hand that wasn't hit, or the one under the hand if they all
+    /// were, O(n) in the worst case.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.ring
+            .iter()
+            .find(|(_, item)| !item.referenced.load(SeqCst))
+            .or_else(|| self.ring.front())
+            .map(|(key, item)| (key, &item.value))
+    }
+
+    /// Updating an existing key keeps its position in the ring and counts as a hit.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        if self.meter.size(new_measure).unwrap_or(1) > self.max_capacity {
+            // It could never fit, so the hand doesn't sweep the ring for it. The value it replaces
+            // is dropped.
+            return self.pop(&k);
+        }
+        self.current_measure = self.meter.add(self.current_measure, new_measure);
+        let old = match self.ring.get_mut(&k) {
+            Some(item) => {
+                item.referenced.store(true, SeqCst);
+                let old = std::mem::replace(&mut item.value, v);
+                self.current_measure = self
+                    .meter
+                    .sub(self.current_measure, self.meter.measure(&k, &old));
+                Some(old)
+            }
+            None => {
+                self.ring.insert(k, Item::new(v));
+                None
+            }
+        };
+        while self.size() > self.capacity() {
+            self.pop_by_policy();
+        }
+        old
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.ring.remove(k).map(|item| {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(k, &item.value));
+            item.value
+        })
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        while let Some((key, item)) = self.ring.pop_front() {
+            if item.referenced.swap(false, SeqCst) {
+                // Second chance, the hand moves past it.
+                self.ring.insert(key, item);
+                continue;
+            }
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&key, &item.value));
+            return Some((key, item.value));
+        }
+        None
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.ring.contains_key(k)
+    }
+
+    fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.max_capacity = capacity;
+    }
+
+    fn size(&self) -> u64 {
+        self.meter
+            .size(self.current_measure)
+            .unwrap_or(self.ring.len() as u64)
+    }
+
+    fn clear(&mut self) {
+        self.ring.clear();
+        self.current_measure = Default::default();
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for Clock<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
@@ -0,0 +1,558 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashbrown::HashTable;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Hot,
+    /// Resident and cold.
+    Cold,
+    /// Cold and evicted, only its key is remembered until its test period ends.
+    Test,
+}
+
+/// The cold pages get 1% of the capacity at first.
+fn initial_cold_target(capacity: u64) -> u64 {
+    Ord::max(capacity / 100, 1)
+}
+
+/// A page of the ring.
+struct Node<K, V> {
+    hash: u64,
+    /// `None` once a cold page is evicted, a test page is only known by its hash.
+    entry: Option<(K, V)>,
+    status: Status,
+    referenced: AtomicBool,
+    /// Size of the item, kept after it's evicted.
+    size: u64,
+    prev: usize,
+    next: usize,
+}
+
+/// A CLOCK-Pro cache, see <https://www.usenix.org/legacy/event/usenix05/tech/general/jiang.html>.
+///
+/// The items are `hot` or `cold` pages of a single ring. A new item is cold, and becomes hot if
+/// it's hit again before the cold hand reaches it. An evicted cold item stays in the ring as a
+/// `test` page, so inserting it again before its test period ends makes it hot right away and
+/// gives the cold pages more room. Three hands go around the ring: the cold one evicts or
+/// promotes the cold pages, the hot one demotes the hot pages not hit since it last passed them,
+/// and the test one ends the oldest test periods.
+///
+/// The capacity is measured by the `Meter`. The cold pages get 1% of it at first, the target
+/// grows by the size of every test hit and shrinks by the size of every test page forgotten
+/// without one. The test pages are bounded by the capacity too.
+pub struct ClockPro<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// Indexes of the pages in `nodes`, by their hash.
+    table: HashTable<usize>,
+    nodes: Vec<Node<K, V>>,
+    hand_hot: Option<usize>,
+    hand_cold: Option<usize>,
+    hand_test: Option<usize>,
+    hot_count: usize,
+    cold_count: usize,
+    test_count: usize,
+    hot_size: u64,
+    cold_size: u64,
+    test_size: u64,
+    /// Room the cold pages get, the hot pages get the rest.
+    cold_target: u64,
+    hash_builder: S,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> ClockPro<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> ClockPro<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> ClockPro<K, V, S, M> {
+    /// Returns a reference to the value of the given key and records the hit.
+    ///
+    /// Only the atomic reference bit of the item is set, so this doesn't need exclusive access.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = &self.nodes[self.find(k)?];
+        let (_, value) = node.entry.as_ref()?;
+        node.referenced.store(true, SeqCst);
+        Some(value)
+    }
+
+    /// Returns the room the cold pages get.
+    pub fn cold_target(&self) -> u64 {
+        self.cold_target
+    }
+
+    /// Checks if the given key is cached as a hot page.
+    pub fn is_hot<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k)
+            .is_some_and(|i| self.nodes[i].status == Status::Hot)
+    }
+
+    /// Returns the number of evicted items still in their test period.
+    pub fn test_len(&self) -> usize {
+        self.test_count
+    }
+
+    /// Finds the page of the given key, resident or in its test period.
+    fn find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.table
+            .find(hash, |&i| match &self.nodes[i].entry {
+                Some((key, _)) => key.borrow() == k,
+                None => self.nodes[i].hash == hash,
+            })
+            .copied()
+    }
+
+    /// Links a new page right behind the hot hand, the last place every hand reaches.
+    fn insert_new(&mut self, hash: u64, entry: Option<(K, V)>, status: Status, size: u64) {
+        let i = self.nodes.len();
+        let (prev, next) = match self.hand_hot {
+            Some(hand) => (self.nodes[hand].prev, hand),
+            None => (i, i),
+        };
+        self.nodes.push(Node {
+            hash,
+            entry,
+            status,
+            referenced: AtomicBool::new(false),
+            size,
+            prev,
+            next,
+        });
+        self.nodes[prev].next = i;
+        self.nodes[next].prev = i;
+        if self.hand_hot.is_none() {
+            self.hand_hot = Some(i);
+            self.hand_cold = Some(i);
+            self.hand_test = Some(i);
+        }
+        let nodes = &self.nodes;
+        self.table.insert_unique(hash, i, |&j| nodes[j].hash);
+        match status {
+            Status::Hot => {
+                self.hot_count += 1;
+                self.hot_size += size;
+            }
+            Status::Cold => {
+                self.cold_count += 1;
+                self.cold_size += size;
+            }
+            Status::Test => {
+                self.test_count += 1;
+                self.test_size += size;
+            }
+        }
+    }
+
+    /// Removes the page at `i` from the ring and returns it, the hands on it move to the next
+    /// page.
+    fn remove_at(&mut self, i: usize) -> Node<K, V> {
+        let Node {
+            hash,
+            status,
+            size,
+            prev,
+            next,
+            ..
+        } = self.nodes[i];
+        self.nodes[prev].next = next;
+        self.nodes[next].prev = prev;
+        let next = (next != i).then_some(next);
+        for hand in [&mut self.hand_hot, &mut self.hand_cold, &mut self.hand_test] {
+            if *hand == Some(i) {
+                *hand = next;
+            }
+        }
+        if let Ok(entry) = self.table.find_entry(hash, |&j| j == i) {
+            entry.remove();
+        }
+        match status {
+            Status::Hot => {
+                self.hot_count -= 1;
+                self.hot_size -= size;
+            }
+            Status::Cold => {
+                self.cold_count -= 1;
+                self.cold_size -= size;
+            }
+            Status::Test => {
+                self.test_count -= 1;
+                self.test_size -= size;
+            }
+        }
+
+        let node = self.nodes.swap_remove(i);
+        if i < self.nodes.len() {
+            // The last page was moved to `i`, fix the links and the hands to it.
+            let moved = self.nodes.len();
+            let Node {
+                hash, prev, next, ..
+            } = self.nodes[i];
+            if prev == moved {
+                // The ring only holds the moved page.
+                self.nodes[i].prev = i;
+                self.nodes[i].next = i;
+            } else {
+                self.nodes[prev].next = i;
+                self.nodes[next].prev = i;
+            }
+            for hand in [&mut self.hand_hot, &mut self.hand_cold, &mut self.hand_test] {
+                if *hand == Some(moved) {
+                    *hand = Some(i);
+                }
+            }
+            if let Some(index) = self.table.find_mut(hash, |&j| j == moved) {
+                *index = i;
+            }
+        }
+        node
+    }
+
+    /// Moves the cold hand one page: a cold page hit since it was last passed becomes hot, any
+    /// other cold page is evicted and returned. Then the hot pages are demoted until they fit
+    /// the room left by the cold pages.
+    fn run_hand_cold(&mut self) -> Option<(K, V)> {
+        let i = self.hand_cold?;
+        let mut evicted = None;
+        let node = &mut self.nodes[i];
+        if node.status == Status::Cold {
+            self.cold_count -= 1;
+            self.cold_size -= node.size;
+            if node.referenced.swap(false, SeqCst) {
+                node.status = Status::Hot;
+                self.hot_count += 1;
+                self.hot_size += node.size;
+            } else {
+                node.status = Status::Test;
+                evicted = node.entry.take();
+                self.test_count += 1;
+                self.test_size += node.size;
+            }
+        }
+        self.hand_cold = Some(self.nodes[i].next);
+        while self.test_size > self.max_capacity && self.test_count > 0 {
+            self.run_hand_test();
+        }
+        while self.hot_size > self.max_capacity.saturating_sub(self.cold_target) {
+            self.run_hand_hot();
+        }
+        evicted
+    }
+
+    /// Moves the hot hand one page, demoting the hot page under it if it wasn't hit since it was
+    /// last passed.
+    fn run_hand_hot(&mut self) {
+        if self.hand_hot == self.hand_test {
+            self.run_hand_test();
+        }
+        let Some(i) = self.hand_hot else {
+            return;
+        };
+        let node = &mut self.nodes[i];
+        if node.status == Status::Hot && !node.referenced.swap(false, SeqCst) {
+            node.status = Status::Cold;
+            self.hot_count -= 1;
+            self.hot_size -= node.size;
+            self.cold_count += 1;
+            self.cold_size += node.size;
+        }
+        self.hand_hot = Some(self.nodes[i].next);
+    }
+
+    /// Moves the test hand one page, ending the test period of the page under it. The cold
+    /// pages missed it, they get less room.
+    fn run_hand_test(&mut self) {
+        let Some(i) = self.hand_test else {
+            return;
+        };
+        if self.nodes[i].status == Status::Test {
+            let node = self.remove_at(i);
+            self.cold_target = Ord::max(self.cold_target.saturating_sub(node.size), 1);
+        } else {
+            self.hand_test = Some(self.nodes[i].next);
+        }
+    }
+
+    /// Updates the resident page at `i` with a value of `new_size`, counting it as a hit.
+    fn update(&mut self, i: usize, v: V, new_size: u64) -> Option<V> {
+        let node = &mut self.nodes[i];
+        let (_, value) = node.entry.as_mut()?;
+        let old = std::mem::replace(value, v);
+        node.referenced.store(true, SeqCst);
+        let old_size = std::mem::replace(&mut node.size, new_size);
+        let size = match node.status {
+            Status::Hot => &mut self.hot_size,
+            _ => &mut self.cold_size,
+        };
+        *size = *size - old_size + new_size;
+        Some(old)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for ClockPro<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            table: HashTable::new(),
+            nodes: Vec::new(),
+            hand_hot: None,
+            hand_cold: None,
+            hand_test: None,
+            hot_count: 0,
+            cold_count: 0,
+            test_count: 0,
+            hot_size: 0,
+            cold_size: 0,
+            test_size: 0,
+            cold_target: initial_cold_target(capacity),
+            hash_builder,
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        ClockPro::get(self, k)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (_, value) = self.nodes[self.find(k)?].entry.as_ref()?;
+        Some(value)
+    }
+
+    /// The first cold page past the cold hand that wasn't hit, or the first resident page if
+    /// there is none, O(n) in the worst case. The hot hand may still demote another page first.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        let start = self.hand_cold?;
+        let mut fallback = None;
+        let mut i = start;
+        loop {
+            let node = &self.nodes[i];
+            if let Some((key, value)) = &node.entry {
+                if node.status == Status::Cold && !node.referenced.load(SeqCst) {
+                    return Some((key, value));
+                }
+                fallback = fallback.or(Some((key, value)));
+            }
+            i = node.next;
+            if i == start {
+                return fallback;
+            }
+        }
+    }
+
+    /// Updating an existing key counts as a hit. Inserting a key in its test period makes it
+    /// hot.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_size = self.meter.size(self.meter.measure(&k, &v)).unwrap_or(1);
+        if new_size > self.max_capacity {
+            // It could never fit, so no page is evicted for it. The value it replaces is dropped,
+            // and a cached key doesn't get a test period.
+            return self.pop(&k);
+        }
+        let mut status = Status::Cold;
+        if let Some(i) = self.find(&k) {
+            if self.nodes[i].entry.is_some() {
+                let old = self.update(i, v, new_size);
+                while self.size() > self.capacity() {
+                    self.pop_by_policy();
+                }
+                return old;
+            }
+            // Evicted too soon, the cold pages get more room.
+            let node = self.remove_at(i);
+            self.cold_target = Ord::min(self.cold_target + node.size, self.max_capacity);
+            status = Status::Hot;
+        }
+        while self.size() + new_size > self.capacity() {
+            if self.pop_by_policy().is_none() {
+                break;
+            }
+        }
+        let hash = self.hash_builder.hash_one(&k);
+        self.insert_new(hash, Some((k, v)), status, new_size);
+        None
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // A test page isn't cached.
+        let i = self.find(k).filter(|&i| self.nodes[i].entry.is_some())?;
+        let (_, value) = self.remove_at(i).entry?;
+        Some(value)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        while !self.is_empty() {
+            if self.cold_count == 0 {
+                // Every resident page is hot, make one cold.
+                self.run_hand_hot();
+            } else if let Some(evicted) = self.run_hand_cold() {
+                return Some(evicted);
+            }
+        }
+        None
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).is_some_and(|i| self.nodes[i].entry.is_some())
+    }
+
+    fn len(&self) -> usize {
+        self.hot_count + self.cold_count
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.max_capacity = capacity;
+        self.cold_target = Ord::min(self.cold_target, capacity);
+        while self.test_size > capacity && self.test_count > 0 {
+            self.run_hand_test();
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.hot_size + self.cold_size
+    }
+
+    fn clear(&mut self) {
+        self.table.clear();
+        self.nodes.clear();
+        self.hand_hot = None;
+        self.hand_cold = None;
+        self.hand_test = None;
+        self.hot_count = 0;
+        self.cold_count = 0;
+        self.test_count = 0;
+        self.hot_size = 0;
+        self.cold_size = 0;
+        self.test_size = 0;
+        self.cold_target = initial_cold_target(self.max_capacity);
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for ClockPro<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::BytesMeter;
+
+    #[test]
+    fn test_ring_consistency() {
+        let seed = rand::random();
+        // Only shown if the test fails, to replay the same operations.
+        println!("seed: {seed}");
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut cache = ClockPro::with_meter(100, BytesMeter);
+        for _ in 0..10_000 {
+            let key = rng.gen_range(0..50_u8);
+            match rng.gen_range(0..4) {
+                0 => {
+                    cache.pop(&key);
+                }
+                1 => {
+                    cache.get(&key);
+                }
+                _ => {
+                    cache.put(key, vec![0u8; rng.gen_range(1..=20)]);
+                }
+            }
+            assert!(cache.size() <= 100);
+            assert!(cache.test_size <= 100);
+            assert!(cache.cold_target >= 1 && cache.cold_target <= 100);
+            let statuses = |status| cache.nodes.iter().filter(move |node| node.status == status);
+            assert_eq!(
+                cache.hot_size,
+                statuses(Status::Hot).map(|node| node.size).sum()
+            );
+            assert_eq!(
+                cache.cold_size,
+                statuses(Status::Cold).map(|node| node.size).sum()
+            );
+            assert_eq!(
+                cache.test_size,
+                statuses(Status::Test).map(|node| node.size).sum()
+            );
+            assert_eq!(cache.len(), cache.nodes.len() - cache.test_count);
+            assert_eq!(cache.table.len(), cache.nodes.len());
+            // The ring links every page once.
+            let mut i = cache.hand_hot.unwrap_or_default();
+            for _ in 0..cache.nodes.len() {
+                assert_eq!(cache.nodes[cache.nodes[i].next].prev, i);
+                i = cache.nodes[i].next;
+            }
+            assert!(cache.nodes.is_empty() || Some(i) == cache.hand_hot);
+        }
+    }
+}
@@ -1,23 +1,47 @@
+mod builder;
+mod checksum;
+mod loader;
+mod policy;
+mod prefetch;
+mod recovery;
+mod writeback;
+
 use std::collections::HashMap;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 
-use dashmap::mapref::one::RefMut;
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::one::Ref;
 use dashmap::DashMap;
-use tokio::fs::OpenOptions;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use hashlink::LinkedHashMap;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+
+use self::checksum::CHUNK_SIZE;
+use self::loader::SharedLoad;
+use self::policy::{BlockAdmission, BlockPolicy};
+use self::writeback::DirtySet;
+
+pub use self::builder::{DiskCacheBuilder, SyncMode};
+pub use self::policy::{AdmissionPolicy, BlockKey, EvictionPolicy};
+pub use self::prefetch::{Loader, Prefetcher};
+pub use self::writeback::Writeback;
 
-type INum = u64;
+/// `INum` is the inode number of a cached file.
+pub type INum = u64;
 
 /// `BlockId` is the offset of the block in the file.
 pub type BlockId = u64;
 
+/// Default size of a block.
 pub const BLOCK_SIZE: usize = 4 * 1024;
 
 /// Block is the basic unit of data in the cache.
+#[derive(Clone)]
 pub struct Block {
     /// Block data
     data: Vec<u8>,
@@ -37,10 +61,54 @@ impl From<Vec<u8>> for Block {
     }
 }
 
-/// `FileCache` is a map from `BlockId` to whether the block exists.
-/// But we don't need the value, so we use bool.
-/// Later we may use a more efficient data structure like a bitset.
-pub type FileCache = Mutex<HashMap<BlockId, bool>>;
+/// The cached blocks of an inum.
+#[derive(Default)]
+pub struct BlockMap {
+    /// `BlockId` -> Block length, the least recently used block first
+    blocks: LinkedHashMap<BlockId, usize>,
+    /// Length of all the blocks
+    size: usize,
+}
+
+impl BlockMap {
+    /// Returns the length of the block.
+    fn get(&self, block_id: &BlockId) -> Option<&usize> {
+        self.blocks.get(block_id)
+    }
+
+    /// Marks the block as the most recently used one.
+    fn touch(&mut self, block_id: &BlockId) {
+        self.blocks.to_back(block_id);
+    }
+
+    /// Inserts a block as the most recently used one, returns the length of the old block.
+    fn insert(&mut self, block_id: BlockId, len: usize) -> Option<usize> {
+        let old_len = self.remove(&block_id);
+        self.blocks.insert(block_id, len);
+        self.size += len;
+        old_len
+    }
+
+    /// Removes the block and returns its length.
+    fn remove(&mut self, block_id: &BlockId) -> Option<usize> {
+        let len = self.blocks.remove(block_id)?;
+        self.size -= len;
+        Some(len)
+    }
+
+    /// Returns the ids of the blocks, the least recently used first.
+    fn keys(&self) -> impl Iterator<Item = &BlockId> {
+        self.blocks.keys()
+    }
+
+    /// Returns `true` if the inum has no cached block.
+    fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// `FileCache` is a map from `BlockId` to the length of the block on disk.
+pub type FileCache = Mutex<BlockMap>;
 
 /// Disk cache size is 1GB
 const DEFAULT_DISK_CACHE_SIZE: usize = 1024 * 1024 * 1024;
@@ -56,28 +124,57 @@ fn path_of_block(base: impl AsRef<Path>, inum: INum, block_id: BlockId) -> PathB
     path_of_inum(base, inum).join(format!("{block_id}"))
 }
 
+/// Returns the path of the temporary file a block is written to before it
+/// replaces the block file.
+fn path_of_tmp_block(base: impl AsRef<Path>, inum: INum, block_id: BlockId) -> PathBuf {
+    path_of_inum(base, inum).join(format!("{block_id}.tmp"))
+}
+
 /// `DiskCache` is a cache for blocks on disk.
 pub struct DiskCache {
-    /// `INum` -> `BlockId` -> Block_existed
+    /// `INum` -> `BlockId` -> Block length
     map: DashMap<INum, FileCache>,
     /// Cache root path
     root_path: PathBuf,
     /// Capacity of the cache
     capacity: usize,
+    /// Size of a block
+    block_size: usize,
     /// Current size of the cache
     size: AtomicUsize,
+    /// When written blocks are synced
+    sync_mode: SyncMode,
+    /// Blocks written since the last batch sync
+    unsynced: parking_lot::Mutex<Vec<PathBuf>>,
+    /// Picks the blocks to evict when the cache is full
+    policy: parking_lot::Mutex<Box<dyn BlockPolicy>>,
+    /// Decides whether a new block is cached when the cache is full, `None` to cache every block
+    admission: Option<parking_lot::Mutex<BlockAdmission>>,
+    /// Quota of the blocks of an inum without its own quota, `None` if unlimited
+    default_inode_quota: Option<usize>,
+    /// Quotas of the inums given their own, `None` if unlimited
+    inode_quotas: parking_lot::Mutex<HashMap<INum, Option<usize>>>,
+    /// Number of blocks found corrupted
+    corruptions: AtomicU64,
+    /// Loads of missing blocks in flight
+    inflight: DashMap<BlockKey, tokio::sync::watch::Receiver<SharedLoad>>,
+    /// Blocks not written back yet, never evicted
+    dirty: parking_lot::Mutex<DirtySet>,
+    /// Held while the dirty set is persisted
+    dirty_persist: Mutex<()>,
 }
 
 impl DiskCache {
     /// Creates a new `DiskCache` with the given root path and default capacity.
+    ///
+    /// The blocks cached under the root path by a previous `DiskCache` are recovered.
     pub async fn open(root_path: impl AsRef<Path>) -> Result<Self> {
-        tokio::fs::create_dir_all(root_path.as_ref()).await?;
-        Ok(DiskCache {
-            map: DashMap::new(),
-            root_path: root_path.as_ref().to_path_buf(),
-            capacity: DEFAULT_DISK_CACHE_SIZE,
-            size: AtomicUsize::new(0),
-        })
+        DiskCacheBuilder::new(root_path).open().await
+    }
+
+    /// Returns a builder of a `DiskCache` at the given root path.
+    pub fn builder(root_path: impl AsRef<Path>) -> DiskCacheBuilder {
+        DiskCacheBuilder::new(root_path)
     }
 
     /// Returns the current size of the cache.
@@ -90,55 +187,597 @@ impl DiskCache {
         self.capacity
     }
 
+    /// Returns the size of a block.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the number of blocks that didn't match their checksums when read, and were
+    /// evicted instead of being served.
+    pub fn corruption_count(&self) -> u64 {
+        self.corruptions.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Returns the quota of the blocks of the given inum in bytes, `None` if unlimited.
+    pub fn inode_quota(&self, inum: INum) -> Option<usize> {
+        self.inode_quotas
+            .lock()
+            .get(&inum)
+            .copied()
+            .unwrap_or(self.default_inode_quota)
+    }
+
+    /// Overrides the default quota of the blocks of the given inum, `None` for unlimited.
+    ///
+    /// The least recently used blocks of the inum are evicted right away if it's over its new
+    /// quota.
+    pub async fn set_inode_quota(&self, inum: INum, quota: Option<usize>) -> Result<()> {
+        if let Some(quota) = quota {
+            if quota < self.block_size {
+                bail!(
+                    "inode quota of {} bytes can't hold a block of {} bytes",
+                    quota,
+                    self.block_size
+                );
+            }
+        }
+        self.inode_quotas.lock().insert(inum, quota);
+        self.apply_inode_quota(inum).await
+    }
+
+    /// Drops the quota of the given inum, so the default one applies again.
+    pub async fn reset_inode_quota(&self, inum: INum) -> Result<()> {
+        self.inode_quotas.lock().remove(&inum);
+        self.apply_inode_quota(inum).await
+    }
+
+    /// Evicts the least recently used blocks of the given inum until it fits its quota.
+    async fn apply_inode_quota(&self, inum: INum) -> Result<()> {
+        if let Some(file_cache_guard) = self.map.get(&inum) {
+            let mut file_cache = file_cache_guard.lock().await;
+            self.make_room_in_quota(&mut file_cache, inum, None, 0)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Evicts the least recently used blocks of the given inum, except `keep`, until `len` more
+    /// bytes fit its quota besides them.
+    ///
+    /// Once an inum is at its quota, its blocks evict among themselves and leave the blocks of
+    /// other inums alone. Dirty blocks are passed over, an inum can go over its quota with them.
+    async fn make_room_in_quota(
+        &self,
+        file_cache: &mut BlockMap,
+        inum: INum,
+        keep: Option<BlockId>,
+        len: usize,
+    ) -> Result<()> {
+        let Some(quota) = self.inode_quota(inum) else {
+            return Ok(());
+        };
+        let kept_len = keep
+            .and_then(|block_id| file_cache.get(&block_id).copied())
+            .unwrap_or(0);
+        while file_cache.size - kept_len + len > quota {
+            let victim = {
+                let dirty = self.dirty.lock();
+                file_cache
+                    .keys()
+                    .copied()
+                    .find(|&block_id| Some(block_id) != keep && !dirty.contains(&(inum, block_id)))
+            };
+            match victim {
+                Some(victim) => self.discard_block(file_cache, inum, victim).await?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
     /// Gets or creates the block map for the given inum for set operation.
-    async fn get_or_create_block_map(&self, inum: INum) -> RefMut<INum, FileCache> {
+    ///
+    /// Only a shared reference is kept. The exclusive one held the shard lock across the block
+    /// write, and a `get` of the shard on the same thread then blocked it for good.
+    async fn get_or_create_block_map(&self, inum: INum) -> Ref<'_, INum, FileCache> {
         // Get or insert
         loop {
             if let Some(entry) = self.map.try_entry(inum) {
-                return entry.or_insert_with(|| Mutex::new(HashMap::new()));
+                return entry
+                    .or_insert_with(|| Mutex::new(BlockMap::default()))
+                    .downgrade();
             }
             // None means the lock is already held by another thread.
             tokio::task::yield_now().await;
         }
     }
 
-    /// Sets the block data for the given inum and `BlockId`.
+    /// Makes room for a block of `len` bytes and starts tracking it in the eviction policy.
+    ///
+    /// Returns the blocks evicted by the policy, the caller has to remove them from disk.
+    fn reserve(&self, key: BlockKey, len: usize) -> Result<Vec<BlockKey>> {
+        if len > self.capacity {
+            bail!(
+                "block of {} bytes doesn't fit in a cache of {} bytes",
+                len,
+                self.capacity
+            );
+        }
+        let mut policy = self.policy.lock();
+        // Don't count the old version of the block twice.
+        let old_len = policy.remove(&key);
+        let Some(victims) = self.evict_clean(&mut **policy, len) else {
+            // The old version is still cached, keep tracking it.
+            if let Some(old_len) = old_len {
+                policy.insert(key, old_len);
+            }
+            bail!(
+                "no room for a block of {} bytes, the cache is full of dirty blocks",
+                len
+            );
+        };
+        policy.insert(key, len as u64);
+        Ok(victims)
+    }
+
+    /// Evicts clean blocks from the policy until `len` more bytes fit the capacity.
+    ///
+    /// Dirty blocks are passed over and stay tracked by the policy. Returns the evicted blocks,
+    /// or `None` if evicting all the clean blocks isn't enough, and then evicts nothing.
+    fn evict_clean(&self, policy: &mut dyn BlockPolicy, len: usize) -> Option<Vec<BlockKey>> {
+        let dirty = self.dirty.lock();
+        let mut victims = Vec::new();
+        let mut passed_over = Vec::new();
+        let mut passed_over_size = 0;
+        while policy.size() + passed_over_size + len as u64 > self.capacity as u64 {
+            match policy.evict() {
+                Some((victim, victim_len)) if dirty.contains(&victim) => {
+                    passed_over.push((victim, victim_len));
+                    passed_over_size += victim_len;
+                }
+                Some(victim) => victims.push(victim),
+                None => break,
+            }
+        }
+        let fits = policy.size() + passed_over_size + len as u64 <= self.capacity as u64;
+        if !fits {
+            passed_over.append(&mut victims);
+        }
+        for (key, len) in passed_over {
+            policy.insert(key, len);
+        }
+        fits.then(|| victims.into_iter().map(|(victim, _)| victim).collect())
+    }
+
+    /// Records an access to the block in the admission filter, if any.
+    fn record_access(&self, key: &BlockKey) {
+        if let Some(admission) = &self.admission {
+            admission.lock().record(key);
+        }
+    }
+
+    /// Checks if the admission filter lets in a write of a block of `len` bytes.
+    ///
+    /// A block that fits without evicting anything, a cached block and a dirty block are always
+    /// let in, the others only if the filter prefers them to the next block to evict.
+    fn admit(&self, key: BlockKey, len: usize) -> bool {
+        let Some(admission) = &self.admission else {
+            return true;
+        };
+        let admission = admission.lock();
+        let policy = self.policy.lock();
+        if policy.size() + len as u64 <= self.capacity as u64
+            || policy.contains(&key)
+            || self.dirty.lock().contains(&key)
+        {
+            return true;
+        }
+        policy
+            .peek()
+            .is_none_or(|victim| admission.admit(&key, &victim))
+    }
+
+    /// Removes an evicted block from disk, the policy no longer tracks it.
+    async fn evict_block(&self, inum: INum, block_id: BlockId) -> Result<()> {
+        if let Some(file_cache_guard) = self.map.get(&inum) {
+            let mut file_cache = file_cache_guard.lock().await;
+            if self.dirty.lock().contains(&(inum, block_id)) {
+                // Marked dirty by `set_dirty` since it was picked, it is tracked again.
+                return Ok(());
+            }
+            if let Some(len) = file_cache.remove(&block_id) {
+                let path = path_of_block(&self.root_path, inum, block_id);
+                tokio::fs::remove_file(path).await?;
+                self.size
+                    .fetch_sub(len, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the block data for the given inum and `BlockId`, replacing the cached block if any.
+    ///
+    /// The block may be shorter than the block size, e.g. the tail block of a file, but not
+    /// empty. If the cache is full, the blocks chosen by the eviction policy are removed first.
+    ///
+    /// The data is written to a temporary file renamed over the block file, so a concurrent or
+    /// later reader sees either the old block or the new one, never a mix of both.
+    ///
+    /// With an `AdmissionPolicy` other than `Always`, a new block the filter rejects isn't
+    /// cached, and this still returns `Ok`.
     pub async fn set(&self, inum: INum, block_id: BlockId, block: &Block) -> Result<()> {
+        self.record_access(&(inum, block_id));
+        self.set_block(inum, block_id, block, true).await
+    }
+
+    /// Sets the block data like `set`, without asking the admission filter.
+    ///
+    /// For a block demoted from a faster tier, which would be lost if it were rejected.
+    pub(crate) async fn set_unfiltered(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        block: &Block,
+    ) -> Result<()> {
+        self.set_block(inum, block_id, block, false).await
+    }
+
+    /// Sets the block data, asking the admission filter if `filtered`, without recording an
+    /// access to the block.
+    async fn set_block(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        block: &Block,
+        filtered: bool,
+    ) -> Result<()> {
+        let len = block.get_data().len();
+        if len == 0 || len > self.block_size {
+            bail!(
+                "block of {} bytes must be within 1 and {} bytes",
+                len,
+                self.block_size
+            );
+        }
+        if filtered && !self.admit((inum, block_id), len) {
+            return Ok(());
+        }
+        let victims = self.reserve((inum, block_id), len)?;
+        let result = async {
+            for (victim_inum, victim_block_id) in victims {
+                self.evict_block(victim_inum, victim_block_id).await?;
+            }
+            self.write_block(inum, block_id, block).await
+        }
+        .await;
+        if result.is_err() {
+            self.restore_tracked_len(inum, block_id).await;
+        }
+        result
+    }
+
+    /// Tracks the block in the policy with the length it has on disk again, or stops tracking it
+    /// if it isn't cached, after a write that reserved a new length for it failed.
+    async fn restore_tracked_len(&self, inum: INum, block_id: BlockId) {
+        let key = (inum, block_id);
+        match self.block_len(inum, block_id).await {
+            Some(len) => self.policy.lock().insert(key, len as u64),
+            None => {
+                self.policy.lock().remove(&key);
+            }
+        }
+    }
+
+    /// Writes the block data to disk and adds it to the block map.
+    async fn write_block(&self, inum: INum, block_id: BlockId, block: &Block) -> Result<()> {
         let file_cache_ref = self.get_or_create_block_map(inum).await;
         let mut file_cache = file_cache_ref.lock().await;
+        let len = block.get_data().len();
+        self.make_room_in_quota(&mut file_cache, inum, Some(block_id), len)
+            .await?;
         // Check if file_cache's directory exists
-        if file_cache.len() == 0 {
+        if file_cache.is_empty() {
             tokio::fs::create_dir_all(path_of_inum(&self.root_path, inum)).await?;
         }
+        self.write_block_file(inum, block_id, block.get_data())
+            .await?;
+        if let Some(old_len) = file_cache.insert(block_id, len) {
+            self.size
+                .fetch_sub(old_len, std::sync::atomic::Ordering::SeqCst);
+        }
+        self.size
+            .fetch_add(len, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Writes the block data and its checksums to a temporary file and renames it over the block
+    /// file.
+    async fn write_block_file(&self, inum: INum, block_id: BlockId, data: &[u8]) -> Result<()> {
+        let tmp_path = path_of_tmp_block(&self.root_path, inum, block_id);
         let path = path_of_block(&self.root_path, inum, block_id);
         let mut file = OpenOptions::new()
             .write(true)
-            .create_new(true)
-            .open(path)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
             .await?;
-        // Apend block data to file
-        file.write_all(block.get_data()).await?;
-        file.sync_all().await?;
-        file_cache.insert(block_id, true);
-        self.size
-            .fetch_add(BLOCK_SIZE, std::sync::atomic::Ordering::SeqCst);
+        file.write_all(data).await?;
+        file.write_all(&checksum::checksums(data)).await?;
+        // Sync before the rename, a crash must not leave a torn block behind.
+        self.sync_written(&file, &path).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
         Ok(())
     }
 
+    /// Syncs a file just written to the block file at `path` according to the sync mode.
+    async fn sync_written(&self, file: &File, path: &Path) -> Result<()> {
+        match self.sync_mode {
+            SyncMode::Always => file.sync_all().await?,
+            SyncMode::Never => {}
+            SyncMode::Batch(batch_size) => {
+                let batch = {
+                    let mut unsynced = self.unsynced.lock();
+                    unsynced.push(path.to_path_buf());
+                    if unsynced.len() >= batch_size {
+                        std::mem::take(&mut *unsynced)
+                    } else {
+                        Vec::new()
+                    }
+                };
+                Self::sync_paths(batch).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the length of the cached block, if any.
+    async fn block_len(&self, inum: INum, block_id: BlockId) -> Option<usize> {
+        let file_cache_guard = self.map.get(&inum)?;
+        let file_cache = file_cache_guard.lock().await;
+        file_cache.get(&block_id).copied()
+    }
+
+    /// Writes `data` at `offset` of a cached block, to keep it coherent with a write to the
+    /// file it caches.
+    ///
+    /// Returns `false` without writing anything if the block isn't cached. The block grows if
+    /// the data ends past its end, up to the block size, and the gap is filled with zeros.
+    pub async fn write_at(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<bool> {
+        let end = offset.checked_add(data.len());
+        let Some(end) = end.filter(|&end| end <= self.block_size) else {
+            bail!(
+                "write of {} bytes at {} goes past the block size of {} bytes",
+                data.len(),
+                offset,
+                self.block_size
+            );
+        };
+        let Some(len) = self.block_len(inum, block_id).await else {
+            return Ok(false);
+        };
+        let result = async {
+            let victims = self.reserve((inum, block_id), Ord::max(len, end))?;
+            for (victim_inum, victim_block_id) in victims {
+                self.evict_block(victim_inum, victim_block_id).await?;
+            }
+
+            let Some(file_cache_guard) = self.map.get(&inum) else {
+                self.policy.lock().remove(&(inum, block_id));
+                return Ok(false);
+            };
+            let mut file_cache = file_cache_guard.lock().await;
+            // The block may have been replaced or removed while making room for it.
+            let Some(len) = file_cache.get(&block_id).copied() else {
+                self.policy.lock().remove(&(inum, block_id));
+                return Ok(false);
+            };
+            // The checksums cover whole chunks, so update the block in a copy of it.
+            let read = self.read_verified(inum, block_id, len, 0, len).await?;
+            let Some(mut block_data) = read else {
+                self.discard_corrupted(&mut file_cache, inum, block_id)
+                    .await?;
+                return Ok(false);
+            };
+            let new_len = Ord::max(len, end);
+            self.make_room_in_quota(&mut file_cache, inum, Some(block_id), new_len)
+                .await?;
+            block_data.resize(new_len, 0);
+            block_data[offset..end].copy_from_slice(data);
+            self.write_block_file(inum, block_id, &block_data).await?;
+
+            file_cache.insert(block_id, new_len);
+            self.policy.lock().insert((inum, block_id), new_len as u64);
+            self.size
+                .fetch_add(new_len - len, std::sync::atomic::Ordering::SeqCst);
+            Ok(true)
+        }
+        .await;
+        if result.is_err() {
+            self.restore_tracked_len(inum, block_id).await;
+        }
+        result
+    }
+
+    /// Syncs the blocks written since the last batch sync.
+    ///
+    /// Only needed with `SyncMode::Batch`, the other modes never leave blocks behind.
+    pub async fn sync(&self) -> Result<()> {
+        let batch = std::mem::take(&mut *self.unsynced.lock());
+        Self::sync_paths(batch).await
+    }
+
+    /// Syncs the given block files, ignoring the ones evicted in the meantime.
+    async fn sync_paths(paths: Vec<PathBuf>) -> Result<()> {
+        for path in paths {
+            match OpenOptions::new().write(true).open(&path).await {
+                Ok(file) => file.sync_all().await?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the range `[start, end)` of a block of `len` bytes and verifies it against the
+    /// checksums of the chunks it spans.
+    ///
+    /// Returns `None` if the block file is corrupted.
+    async fn read_verified(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        len: usize,
+        start: usize,
+        end: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let first_chunk = start / CHUNK_SIZE;
+        let last_chunk = end.div_ceil(CHUNK_SIZE);
+        let span_start = first_chunk * CHUNK_SIZE;
+        let span_end = Ord::min(last_chunk * CHUNK_SIZE, len);
+        let checksums_start = checksum::checksum_offset(len, first_chunk);
+        let checksums_end = checksum::checksum_offset(len, last_chunk);
+        let path = path_of_block(&self.root_path, inum, block_id);
+        let read = tokio::task::spawn_blocking(move || -> std::io::Result<_> {
+            let file = std::fs::File::open(path)?;
+            let mut data = vec![0; span_end - span_start];
+            file.read_exact_at(&mut data, span_start as u64)?;
+            let mut checksums = vec![0; checksums_end - checksums_start];
+            file.read_exact_at(&mut checksums, checksums_start as u64)?;
+            Ok((data, checksums))
+        })
+        .await?;
+        let (mut data, checksums) = match read {
+            Ok(read) => read,
+            // A truncated block file is as corrupted as one with a bad checksum.
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if !checksum::verify(&data, &checksums) {
+            return Ok(None);
+        }
+        data.truncate(end - span_start);
+        data.drain(..start - span_start);
+        Ok(Some(data))
+    }
+
+    /// Removes a block from the block map, the eviction policy, the dirty set and disk.
+    async fn discard_block(
+        &self,
+        file_cache: &mut BlockMap,
+        inum: INum,
+        block_id: BlockId,
+    ) -> Result<()> {
+        if let Some(len) = file_cache.remove(&block_id) {
+            self.policy.lock().remove(&(inum, block_id));
+            self.size
+                .fetch_sub(len, std::sync::atomic::Ordering::SeqCst);
+            let path = path_of_block(&self.root_path, inum, block_id);
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+            if self.dirty.lock().remove(&(inum, block_id)) {
+                self.persist_dirty().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts a block that didn't match its checksums and counts it.
+    async fn discard_corrupted(
+        &self,
+        file_cache: &mut BlockMap,
+        inum: INum,
+        block_id: BlockId,
+    ) -> Result<()> {
+        self.corruptions
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.discard_block(file_cache, inum, block_id).await
+    }
+
     /// Gets the block data for the given inum and `BlockId`.
+    ///
+    /// A block that doesn't match its checksums is evicted and reported as a miss.
     pub async fn get(&self, inum: INum, block_id: BlockId) -> Result<Option<Block>> {
+        self.record_access(&(inum, block_id));
+        self.get_block(inum, block_id).await
+    }
+
+    /// Gets the block data like `get`, without recording an access to the block.
+    async fn get_block(&self, inum: INum, block_id: BlockId) -> Result<Option<Block>> {
         if let Some(file_cache_guard) = self.map.get(&inum) {
-            let file_cache = file_cache_guard.lock().await;
-            if let Some(existed) = file_cache.get(&block_id) {
-                if *existed {
-                    let mut file = OpenOptions::new()
-                        .read(true)
-                        .open(path_of_block(&self.root_path, inum, block_id))
+            let mut file_cache = file_cache_guard.lock().await;
+            if let Some(&len) = file_cache.get(&block_id) {
+                let Some(data) = self.read_verified(inum, block_id, len, 0, len).await? else {
+                    self.discard_corrupted(&mut file_cache, inum, block_id)
                         .await?;
-                    let mut data = vec![0; BLOCK_SIZE];
-                    file.read_exact(&mut data).await?;
-                    return Ok(Some(Block::from(data)));
+                    return Ok(None);
+                };
+                file_cache.touch(&block_id);
+                self.policy.lock().touch(&(inum, block_id));
+                return Ok(Some(Block::from(data)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets at most `len` bytes of the block data for the given inum and `BlockId`, starting at
+    /// `offset`.
+    ///
+    /// Only the checksum chunks spanned by the requested range are read. It's cut short at the end
+    /// of the block, so the returned block is shorter than `len`, or even empty, if the range goes
+    /// past it.
+    pub async fn get_range(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Block>> {
+        let mut data = vec![0; len.min(self.block_size.saturating_sub(offset))];
+        let read = self.read_into(inum, block_id, offset, &mut data).await?;
+        Ok(read.map(|read| {
+            data.truncate(read);
+            Block::from(data)
+        }))
+    }
+
+    /// Reads the block data for the given inum and `BlockId` into `buf`, starting at `offset`.
+    ///
+    /// Returns the number of bytes read, which is less than the length of `buf` if the block
+    /// ends first, or `None` if the block isn't cached or is corrupted.
+    pub async fn read_into(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>> {
+        self.record_access(&(inum, block_id));
+        if let Some(file_cache_guard) = self.map.get(&inum) {
+            let mut file_cache = file_cache_guard.lock().await;
+            if let Some(&len) = file_cache.get(&block_id) {
+                let read_len = len.saturating_sub(offset).min(buf.len());
+                if read_len > 0 {
+                    let end = offset + read_len;
+                    let Some(data) = self.read_verified(inum, block_id, len, offset, end).await?
+                    else {
+                        self.discard_corrupted(&mut file_cache, inum, block_id)
+                            .await?;
+                        return Ok(None);
+                    };
+                    buf[..read_len].copy_from_slice(&data);
                 }
+                file_cache.touch(&block_id);
+                self.policy.lock().touch(&(inum, block_id));
+                return Ok(Some(read_len));
             }
         }
         Ok(None)
@@ -148,24 +787,80 @@ impl DiskCache {
     pub async fn remove_block(&self, inum: INum, block_id: BlockId) -> Result<()> {
         if let Some(file_cache_guard) = self.map.get(&inum) {
             let mut file_cache = file_cache_guard.lock().await;
-            if let Some(existed) = file_cache.get(&block_id) {
-                if *existed {
-                    let path = path_of_block(&self.root_path, inum, block_id);
-                    tokio::fs::remove_file(path).await?;
-                    file_cache.remove(&block_id);
-                    self.size
-                        .fetch_sub(BLOCK_SIZE, std::sync::atomic::Ordering::SeqCst);
-                }
+            self.discard_block(&mut file_cache, inum, block_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the cached blocks of the given inum, in ascending order.
+    pub async fn list_blocks(&self, inum: INum) -> Vec<BlockId> {
+        let Some(file_cache_guard) = self.map.get(&inum) else {
+            return Vec::new();
+        };
+        let file_cache = file_cache_guard.lock().await;
+        let mut block_ids: Vec<_> = file_cache.keys().copied().collect();
+        block_ids.sort_unstable();
+        block_ids
+    }
+
+    /// Removes all the cached blocks of the given inum, e.g. when its file is deleted.
+    pub async fn remove_file(&self, inum: INum) -> Result<()> {
+        self.truncate(inum, 0).await
+    }
+
+    /// Removes the cached blocks of the given inum from `from_block` on, e.g. when its file is
+    /// truncated.
+    ///
+    /// The inum directory is removed once it holds no block anymore.
+    pub async fn truncate(&self, inum: INum, from_block: BlockId) -> Result<()> {
+        {
+            let Some(file_cache_guard) = self.map.get(&inum) else {
+                return Ok(());
+            };
+            let mut file_cache = file_cache_guard.lock().await;
+            let block_ids: Vec<_> = file_cache
+                .keys()
+                .copied()
+                .filter(|&block_id| block_id >= from_block)
+                .collect();
+            for block_id in block_ids {
+                self.discard_block(&mut file_cache, inum, block_id).await?;
+            }
+            if !file_cache.is_empty() {
+                return Ok(());
+            }
+            // `write_block` recreates the directory for the next block of an empty block map.
+            match tokio::fs::remove_dir(path_of_inum(&self.root_path, inum)).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
             }
         }
+        self.prune_block_map(inum);
         Ok(())
     }
 
-    /// Clears the cache.
+    /// Drops the block map of the given inum if it is empty and nobody is using it.
+    fn prune_block_map(&self, inum: INum) {
+        // Don't wait for the entry, another task may hold the shard across an await.
+        if let Some(Entry::Occupied(entry)) = self.map.try_entry(inum) {
+            let is_empty = entry
+                .get()
+                .try_lock()
+                .is_ok_and(|file_cache| file_cache.is_empty());
+            if is_empty {
+                entry.remove();
+            }
+        }
+    }
+
+    /// Clears the cache, dirty blocks included.
     pub async fn clear(&self) -> Result<()> {
         tokio::fs::remove_dir_all(&self.root_path).await?;
         tokio::fs::create_dir_all(&self.root_path).await?;
         self.map.clear();
+        self.policy.lock().clear();
+        self.dirty.lock().clear();
         self.size.store(0, std::sync::atomic::Ordering::SeqCst);
         Ok(())
     }
@@ -177,6 +872,11 @@ mod tests {
 
     use super::*;
 
+    /// Returns the content of the block file of a block.
+    fn block_file(data: &[u8]) -> Vec<u8> {
+        [data, &checksum::checksums(data)].concat()
+    }
+
     /// Test basic set and get operations.
     #[tokio::test]
     #[allow(clippy::unwrap_used)]
@@ -233,4 +933,520 @@ mod tests {
         }
         disk_cache.clear().await.unwrap();
     }
+
+    /// Test that a `get` doesn't wait on a `set` of the same inum writing its block.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_set_while_get() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = Arc::new(DiskCache::open(tempdir).await.unwrap());
+        let block = Block::from(vec![1; 10]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        let mut tasks = Vec::new();
+        for block_id in 1..16 {
+            let setter = Arc::<DiskCache>::clone(&disk_cache);
+            tasks.push(tokio::spawn(async move {
+                let block = Block::from(vec![1; 10]);
+                setter.set(1, block_id, &block).await.unwrap();
+            }));
+            let getter = Arc::<DiskCache>::clone(&disk_cache);
+            tasks.push(tokio::spawn(async move {
+                let block = getter.get(1, 0).await.unwrap().unwrap();
+                assert_eq!(block.get_data(), &[1; 10]);
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(disk_cache.list_blocks(1).await.len(), 16);
+    }
+
+    /// Test that a failed `write_at` leaves the block and its length in the policy as they were.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_write_at_failure() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        disk_cache
+            .set(1, 0, &Block::from(vec![1; 10]))
+            .await
+            .unwrap();
+        // The block can't be rewritten while a directory is in the way of its temporary file.
+        let tmp_path = path_of_tmp_block(tempdir.path(), 1, 0);
+        std::fs::create_dir(&tmp_path).unwrap();
+        assert!(disk_cache.write_at(1, 0, 10, &[2; 10]).await.is_err());
+        assert_eq!(disk_cache.policy.lock().size(), 10);
+        assert_eq!(disk_cache.size(), 10);
+        let block = disk_cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(block.get_data(), &[1; 10]);
+
+        std::fs::remove_dir(&tmp_path).unwrap();
+        assert!(disk_cache.write_at(1, 0, 10, &[2; 10]).await.unwrap());
+        assert_eq!(disk_cache.policy.lock().size(), 20);
+    }
+
+    /// Test that a failed `set` leaves the block it replaces and its length in the policy.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_set_failure() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        disk_cache
+            .set(1, 0, &Block::from(vec![1; 10]))
+            .await
+            .unwrap();
+        // The block can't be rewritten while a directory is in the way of its temporary file.
+        std::fs::create_dir(path_of_tmp_block(tempdir.path(), 1, 0)).unwrap();
+        let block = Block::from(vec![2; 20]);
+        assert!(disk_cache.set(1, 0, &block).await.is_err());
+        assert!(disk_cache.policy.lock().contains(&(1, 0)));
+        assert_eq!(disk_cache.policy.lock().size(), 10);
+        let read = disk_cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[1; 10]);
+    }
+
+    /// Test that a `set` finding no room keeps tracking the block it would have replaced.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_reserve_failure() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![1; BLOCK_SIZE - 10]);
+        disk_cache.set_dirty(1, 0, &block).await.unwrap();
+        disk_cache.set_dirty(1, 1, &block).await.unwrap();
+        disk_cache
+            .set(1, 2, &Block::from(vec![1; 20]))
+            .await
+            .unwrap();
+        // Only dirty blocks are left to evict for the grown block.
+        let block = Block::from(vec![2; 30]);
+        assert!(disk_cache.set(1, 2, &block).await.is_err());
+        assert!(disk_cache.policy.lock().contains(&(1, 2)));
+        assert_eq!(disk_cache.policy.lock().size(), 2 * BLOCK_SIZE as u64);
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 1, 2]);
+    }
+
+    /// Test that `set` evicts blocks once the cache is full.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_evict() {
+        for policy in [
+            EvictionPolicy::Fifo,
+            EvictionPolicy::Lru,
+            EvictionPolicy::S3Fifo,
+        ] {
+            let tempdir = tempfile::tempdir().unwrap();
+            let disk_cache = DiskCache::builder(&tempdir)
+                .capacity(4 * BLOCK_SIZE)
+                .eviction_policy(policy)
+                .open()
+                .await
+                .unwrap();
+            let block = Block::from(vec![0; BLOCK_SIZE]);
+            for block_id in 0..10 {
+                disk_cache.set(1, block_id, &block).await.unwrap();
+                assert!(disk_cache.size() <= disk_cache.capacity());
+            }
+            assert_eq!(disk_cache.size(), 4 * BLOCK_SIZE);
+            for block_id in 0..6 {
+                assert!(disk_cache.get(1, block_id).await.unwrap().is_none());
+                let path = path_of_block(tempdir.path(), 1, block_id);
+                assert!(!path.exists(), "{policy:?} left {path:?} on disk");
+            }
+            for block_id in 6..10 {
+                assert!(disk_cache.get(1, block_id).await.unwrap().is_some());
+            }
+        }
+    }
+
+    /// Test that a block read again survives an LRU eviction.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_evict_lru() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .eviction_policy(EvictionPolicy::Lru)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![0; BLOCK_SIZE]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        disk_cache.set(1, 1, &block).await.unwrap();
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+        assert!(disk_cache.get(1, 1).await.unwrap().is_none());
+    }
+
+    /// Test that a TinyLFU admission filter only caches a new block accessed more often than
+    /// the next block to evict.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_admission() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .admission_policy(AdmissionPolicy::TinyLfu)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![0; BLOCK_SIZE]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        disk_cache.set(1, 1, &block).await.unwrap();
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+
+        // Seen as often as block 1, block 2 isn't worth evicting it.
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert!(disk_cache.get(1, 2).await.unwrap().is_none());
+        assert!(!path_of_block(tempdir.path(), 1, 2).exists());
+        assert_eq!(disk_cache.size(), 2 * BLOCK_SIZE);
+
+        // Missed then written again, it is now.
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert!(disk_cache.get(1, 2).await.unwrap().is_some());
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+        assert!(disk_cache.get(1, 1).await.unwrap().is_none());
+
+        // A cached block is always updated.
+        let block = Block::from(vec![1; BLOCK_SIZE]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        assert_eq!(
+            disk_cache.get(1, 0).await.unwrap().unwrap().get_data(),
+            block.get_data()
+        );
+    }
+
+    /// Test a cache with larger blocks and batched syncs.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_builder() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let block_size = 64 * 1024;
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(3 * block_size)
+            .block_size(block_size)
+            .sync_mode(SyncMode::Batch(2))
+            .eviction_policy(EvictionPolicy::Fifo)
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(disk_cache.capacity(), 3 * block_size);
+        assert_eq!(disk_cache.block_size(), block_size);
+        let block = Block::from(vec![1; block_size]);
+        for block_id in 0..3 {
+            disk_cache.set(1, block_id, &block).await.unwrap();
+        }
+        assert_eq!(disk_cache.unsynced.lock().len(), 1);
+        disk_cache.sync().await.unwrap();
+        assert!(disk_cache.unsynced.lock().is_empty());
+        let read = disk_cache.get(1, 2).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), block.get_data());
+
+        assert!(DiskCache::builder(&tempdir)
+            .capacity(block_size - 1)
+            .block_size(block_size)
+            .open()
+            .await
+            .is_err());
+        assert!(DiskCache::builder(&tempdir)
+            .sync_mode(SyncMode::Batch(0))
+            .open()
+            .await
+            .is_err());
+    }
+
+    /// Test that reopening a cache recovers its blocks and discards malformed files.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_recover() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let block = Block::from(vec![7; BLOCK_SIZE]);
+        {
+            let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+            for block_id in 0..4 {
+                disk_cache.set(1, block_id, &block).await.unwrap();
+            }
+            disk_cache.set(2, 0, &block).await.unwrap();
+        }
+        // An incomplete block, a file that isn't a block and a directory that isn't an inum.
+        std::fs::write(path_of_block(tempdir.path(), 2, 1), b"").unwrap();
+        std::fs::write(path_of_inum(tempdir.path(), 2).join("foo"), [7; 10]).unwrap();
+        std::fs::create_dir(tempdir.path().join("bar")).unwrap();
+        std::fs::create_dir(path_of_inum(tempdir.path(), 3)).unwrap();
+
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        assert_eq!(disk_cache.size(), 5 * BLOCK_SIZE);
+        for block_id in 0..4 {
+            let read = disk_cache.get(1, block_id).await.unwrap().unwrap();
+            assert_eq!(read.get_data(), block.get_data());
+        }
+        assert!(disk_cache.get(2, 0).await.unwrap().is_some());
+        assert!(disk_cache.get(2, 1).await.unwrap().is_none());
+        assert!(!path_of_block(tempdir.path(), 2, 1).exists());
+        assert!(!path_of_inum(tempdir.path(), 2).join("foo").exists());
+        assert!(!tempdir.path().join("bar").exists());
+        assert!(!path_of_inum(tempdir.path(), 3).exists());
+
+        // Recovered blocks are evicted if they don't fit anymore, the oldest first.
+        drop(disk_cache);
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .eviction_policy(EvictionPolicy::Fifo)
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(disk_cache.size(), 2 * BLOCK_SIZE);
+        assert!(disk_cache.get(2, 0).await.unwrap().is_some());
+    }
+
+    /// Test that short blocks round-trip and are accounted by their length.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_block_len() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .open()
+            .await
+            .unwrap();
+        let tail = Block::from(vec![3; 100]);
+        disk_cache.set(1, 0, &tail).await.unwrap();
+        assert_eq!(disk_cache.size(), 100);
+        let read = disk_cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), tail.get_data());
+
+        // Two full blocks only fit once the tail block is evicted.
+        let block = Block::from(vec![4; BLOCK_SIZE]);
+        disk_cache.set(1, 1, &block).await.unwrap();
+        assert_eq!(disk_cache.size(), BLOCK_SIZE + 100);
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert_eq!(disk_cache.size(), 2 * BLOCK_SIZE);
+        assert!(disk_cache.get(1, 0).await.unwrap().is_none());
+
+        let too_large = Block::from(vec![0; BLOCK_SIZE + 1]);
+        assert!(disk_cache.set(1, 3, &too_large).await.is_err());
+        assert!(disk_cache
+            .set(1, 3, &Block::from(Vec::new()))
+            .await
+            .is_err());
+
+        // Short blocks are recovered with their length.
+        drop(disk_cache);
+        std::fs::write(path_of_block(tempdir.path(), 1, 0), block_file(&[3; 100])).unwrap();
+        let too_large = block_file(&[0; BLOCK_SIZE + 1]);
+        std::fs::write(path_of_block(tempdir.path(), 1, 4), too_large).unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        assert_eq!(disk_cache.size(), 2 * BLOCK_SIZE + 100);
+        let read = disk_cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), tail.get_data());
+        assert!(!path_of_block(tempdir.path(), 1, 4).exists());
+    }
+
+    /// Test replacing a block and updating part of it.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_overwrite() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        disk_cache
+            .set(1, 0, &Block::from(vec![1; BLOCK_SIZE]))
+            .await
+            .unwrap();
+        disk_cache
+            .set(1, 0, &Block::from(vec![2; 100]))
+            .await
+            .unwrap();
+        assert_eq!(disk_cache.size(), 100);
+        let read = disk_cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[2; 100]);
+
+        // Update in the middle of the block, then past its end.
+        assert!(disk_cache.write_at(1, 0, 10, &[3; 10]).await.unwrap());
+        assert!(disk_cache.write_at(1, 0, 150, &[4; 50]).await.unwrap());
+        assert_eq!(disk_cache.size(), 200);
+        let read = disk_cache.get(1, 0).await.unwrap().unwrap();
+        let mut expected = vec![2; 100];
+        expected[10..20].fill(3);
+        expected.resize(150, 0);
+        expected.resize(200, 4);
+        assert_eq!(read.get_data(), expected.as_slice());
+
+        assert!(!disk_cache.write_at(1, 1, 0, &[5; 10]).await.unwrap());
+        assert!(disk_cache.get(1, 1).await.unwrap().is_none());
+        assert!(disk_cache
+            .write_at(1, 0, BLOCK_SIZE - 1, &[5; 2])
+            .await
+            .is_err());
+        // An offset so large the end overflows is past the block too.
+        assert!(disk_cache
+            .write_at(1, 0, usize::MAX, &[5; 2])
+            .await
+            .is_err());
+        assert!(!path_of_tmp_block(tempdir.path(), 1, 0).exists());
+    }
+
+    /// Test reading part of a block.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_range() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        let data: Vec<u8> = (0..200).collect();
+        disk_cache
+            .set(1, 0, &Block::from(data.clone()))
+            .await
+            .unwrap();
+
+        let read = disk_cache.get_range(1, 0, 10, 20).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &data[10..30]);
+        // Ranges are cut short at the end of the block.
+        let read = disk_cache.get_range(1, 0, 190, 20).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &data[190..]);
+        let read = disk_cache.get_range(1, 0, 300, 20).await.unwrap().unwrap();
+        assert!(read.get_data().is_empty());
+        assert!(disk_cache.get_range(1, 1, 0, 20).await.unwrap().is_none());
+
+        let mut buf = [0; 16];
+        let read = disk_cache.read_into(1, 0, 100, &mut buf).await.unwrap();
+        assert_eq!(read, Some(16));
+        assert_eq!(&buf, &data[100..116]);
+        let read = disk_cache.read_into(1, 0, 196, &mut buf).await.unwrap();
+        assert_eq!(read, Some(4));
+        assert_eq!(&buf[..4], &data[196..]);
+        assert_eq!(disk_cache.read_into(2, 0, 0, &mut buf).await.unwrap(), None);
+    }
+
+    /// Test that corrupted blocks are evicted instead of being served.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_corruption() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .block_size(4 * CHUNK_SIZE)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![1; 4 * CHUNK_SIZE]);
+        for block_id in 0..3 {
+            disk_cache.set(1, block_id, &block).await.unwrap();
+        }
+        // Flip a byte of the third chunk of every block and truncate the last one.
+        for block_id in 0..3 {
+            let path = path_of_block(tempdir.path(), 1, block_id);
+            let mut data = std::fs::read(&path).unwrap();
+            data[2 * CHUNK_SIZE + 1] ^= 0xff;
+            if block_id == 2 {
+                data.truncate(4 * CHUNK_SIZE);
+            }
+            std::fs::write(&path, data).unwrap();
+        }
+
+        // Ranges within intact chunks are still served.
+        let read = disk_cache.get_range(1, 0, 10, CHUNK_SIZE).await.unwrap();
+        assert_eq!(read.unwrap().get_data(), &[1; CHUNK_SIZE]);
+        assert_eq!(disk_cache.corruption_count(), 0);
+
+        assert!(disk_cache.get(1, 0).await.unwrap().is_none());
+        let mut buf = [0; 16];
+        let read = disk_cache
+            .read_into(1, 1, 2 * CHUNK_SIZE, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(read, None);
+        assert!(!disk_cache.write_at(1, 2, 0, &[2; 10]).await.unwrap());
+        assert_eq!(disk_cache.corruption_count(), 3);
+        assert_eq!(disk_cache.size(), 0);
+        for block_id in 0..3 {
+            assert!(!path_of_block(tempdir.path(), 1, block_id).exists());
+            assert!(disk_cache.get(1, block_id).await.unwrap().is_none());
+        }
+    }
+
+    /// Test removing all or part of the blocks of an inum.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_per_inode() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        let block = Block::from(vec![1; BLOCK_SIZE]);
+        for block_id in [3, 0, 2, 1] {
+            disk_cache.set(1, block_id, &block).await.unwrap();
+        }
+        disk_cache.set(2, 0, &block).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 1, 2, 3]);
+        assert!(disk_cache.list_blocks(3).await.is_empty());
+
+        disk_cache.truncate(1, 2).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 1]);
+        assert_eq!(disk_cache.size(), 3 * BLOCK_SIZE);
+        assert!(!path_of_block(tempdir.path(), 1, 2).exists());
+        assert!(disk_cache.get(1, 3).await.unwrap().is_none());
+
+        disk_cache.remove_file(1).await.unwrap();
+        assert!(disk_cache.list_blocks(1).await.is_empty());
+        assert_eq!(disk_cache.size(), BLOCK_SIZE);
+        assert!(!path_of_inum(tempdir.path(), 1).exists());
+        assert!(disk_cache.get(2, 0).await.unwrap().is_some());
+
+        // The inum can be cached again.
+        disk_cache.set(1, 0, &block).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0]);
+        disk_cache.truncate(2, 0).await.unwrap();
+        assert!(!path_of_inum(tempdir.path(), 2).exists());
+        assert_eq!(disk_cache.size(), BLOCK_SIZE);
+    }
+
+    /// Test that an inum at its quota evicts its own blocks.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_disk_cache_inode_quota() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(8 * BLOCK_SIZE)
+            .inode_quota(2 * BLOCK_SIZE)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![1; BLOCK_SIZE]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        disk_cache.set(1, 1, &block).await.unwrap();
+        disk_cache.get(1, 0).await.unwrap();
+        // A long scan of inum 2 only evicts its own blocks, the least recently used first.
+        for block_id in 0..6 {
+            disk_cache.set(2, block_id, &block).await.unwrap();
+        }
+        assert_eq!(disk_cache.list_blocks(2).await, vec![4, 5]);
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 2]);
+        assert_eq!(disk_cache.size(), 4 * BLOCK_SIZE);
+
+        // Replacing a block or growing it doesn't count it twice.
+        disk_cache
+            .set(1, 2, &Block::from(vec![2; 10]))
+            .await
+            .unwrap();
+        assert!(disk_cache.write_at(1, 2, 10, &[2; 10]).await.unwrap());
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 2]);
+
+        // A per-inode quota overrides the default one and applies right away.
+        disk_cache
+            .set_inode_quota(2, Some(BLOCK_SIZE))
+            .await
+            .unwrap();
+        assert_eq!(disk_cache.list_blocks(2).await, vec![5]);
+        disk_cache.set_inode_quota(1, None).await.unwrap();
+        for block_id in 3..6 {
+            disk_cache.set(1, block_id, &block).await.unwrap();
+        }
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 2, 3, 4, 5]);
+        disk_cache.reset_inode_quota(1).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![4, 5]);
+        assert_eq!(disk_cache.inode_quota(1), Some(2 * BLOCK_SIZE));
+        assert!(disk_cache.set_inode_quota(1, Some(10)).await.is_err());
+    }
 }
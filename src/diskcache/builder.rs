@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+
+use super::writeback::DirtySet;
+use super::{AdmissionPolicy, DiskCache, EvictionPolicy, BLOCK_SIZE, DEFAULT_DISK_CACHE_SIZE};
+
+/// When `DiskCache` flushes written blocks to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Syncs every block as soon as it is written.
+    #[default]
+    Always,
+    /// Never syncs, the OS writes the blocks back whenever it wants.
+    Never,
+    /// Syncs the written blocks in a batch every time this many blocks have been written.
+    Batch(usize),
+}
+
+/// Builder of a `DiskCache`.
+#[derive(Debug, Clone)]
+pub struct DiskCacheBuilder {
+    /// Cache root path
+    root_path: PathBuf,
+    /// Capacity of the cache in bytes
+    capacity: usize,
+    /// Size of a block in bytes
+    block_size: usize,
+    /// When written blocks are synced
+    sync_mode: SyncMode,
+    /// Picks the blocks to evict when the cache is full
+    eviction_policy: EvictionPolicy,
+    /// Decides whether a new block is cached when the cache is full
+    admission_policy: AdmissionPolicy,
+    /// Quota of the blocks of an inum in bytes, `None` if unlimited
+    inode_quota: Option<usize>,
+}
+
+impl DiskCacheBuilder {
+    /// Creates a builder of a `DiskCache` at the given root path, with a capacity of 1GB, blocks of
+    /// `BLOCK_SIZE` bytes, synced on every write and evicted by LRU, caching every block.
+    pub fn new(root_path: impl AsRef<Path>) -> Self {
+        Self {
+            root_path: root_path.as_ref().to_path_buf(),
+            capacity: DEFAULT_DISK_CACHE_SIZE,
+            block_size: BLOCK_SIZE,
+            sync_mode: SyncMode::default(),
+            eviction_policy: EvictionPolicy::default(),
+            admission_policy: AdmissionPolicy::default(),
+            inode_quota: None,
+        }
+    }
+
+    /// Sets the capacity of the cache in bytes.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the size of a block in bytes.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets when written blocks are synced to the disk.
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Sets the policy picking the blocks to evict when the cache is full.
+    pub fn eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Sets the filter deciding whether a new block is cached when the cache is full.
+    pub fn admission_policy(mut self, admission_policy: AdmissionPolicy) -> Self {
+        self.admission_policy = admission_policy;
+        self
+    }
+
+    /// Sets the quota of the blocks of every inum in bytes, unlimited by default.
+    ///
+    /// An inum at its quota evicts its own least recently used blocks instead of the blocks of
+    /// other inums. `DiskCache::set_inode_quota` overrides it for a given inum.
+    pub fn inode_quota(mut self, inode_quota: usize) -> Self {
+        self.inode_quota = Some(inode_quota);
+        self
+    }
+
+    /// Creates the cache root directory if needed and opens the `DiskCache`, recovering the
+    /// blocks cached under it by a previous `DiskCache`.
+    pub async fn open(self) -> Result<DiskCache> {
+        if self.block_size == 0 {
+            bail!("block size must not be zero");
+        }
+        if self.capacity < self.block_size {
+            bail!(
+                "capacity of {} bytes can't hold a block of {} bytes",
+                self.capacity,
+                self.block_size
+            );
+        }
+        if let Some(inode_quota) = self.inode_quota {
+            if inode_quota < self.block_size {
+                bail!(
+                    "inode quota of {} bytes can't hold a block of {} bytes",
+                    inode_quota,
+                    self.block_size
+                );
+            }
+        }
+        if self.sync_mode == SyncMode::Batch(0) {
+            bail!("sync batch size must not be zero");
+        }
+        tokio::fs::create_dir_all(&self.root_path).await?;
+        let disk_cache = DiskCache {
+            map: DashMap::new(),
+            root_path: self.root_path,
+            capacity: self.capacity,
+            block_size: self.block_size,
+            size: AtomicUsize::new(0),
+            sync_mode: self.sync_mode,
+            unsynced: parking_lot::Mutex::new(Vec::new()),
+            policy: parking_lot::Mutex::new(self.eviction_policy.build()),
+            admission: self
+                .admission_policy
+                .build(self.capacity / self.block_size)
+                .map(parking_lot::Mutex::new),
+            default_inode_quota: self.inode_quota,
+            inode_quotas: parking_lot::Mutex::new(HashMap::new()),
+            corruptions: AtomicU64::new(0),
+            inflight: DashMap::new(),
+            dirty: parking_lot::Mutex::new(DirtySet::default()),
+            dirty_persist: tokio::sync::Mutex::new(()),
+        };
+        disk_cache.recover().await?;
+        Ok(disk_cache)
+    }
+}
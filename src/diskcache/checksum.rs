@@ -0,0 +1,48 @@
+/// Size of the chunks of a block covered by a checksum.
+///
+/// A block file holds the block data followed by the CRC32C of every chunk of it, so a range of
+/// a block can be verified without reading the whole block.
+pub(super) const CHUNK_SIZE: usize = 4 * 1024;
+
+/// Size of a checksum.
+const CHECKSUM_SIZE: usize = 4;
+
+/// Returns the number of chunks of a block of `len` bytes.
+fn chunks(len: usize) -> usize {
+    len.div_ceil(CHUNK_SIZE)
+}
+
+/// Returns the size of the checksums of a block of `len` bytes.
+pub(super) fn checksums_len(len: usize) -> usize {
+    chunks(len) * CHECKSUM_SIZE
+}
+
+/// Returns the offset in the block file of the checksum of the chunk at `chunk`.
+pub(super) fn checksum_offset(len: usize, chunk: usize) -> usize {
+    len + chunk * CHECKSUM_SIZE
+}
+
+/// Returns the length of the block stored in a block file of `file_len` bytes, `None` if no
+/// block fits it exactly.
+pub(super) fn block_len(file_len: usize) -> Option<usize> {
+    let chunks = file_len.div_ceil(CHUNK_SIZE + CHECKSUM_SIZE);
+    let len = file_len.checked_sub(chunks * CHECKSUM_SIZE)?;
+    (len > 0 && checksums_len(len) + len == file_len).then_some(len)
+}
+
+/// Returns the checksums of every chunk of `data`.
+pub(super) fn checksums(data: &[u8]) -> Vec<u8> {
+    data.chunks(CHUNK_SIZE)
+        .flat_map(|chunk| crc32c::crc32c(chunk).to_le_bytes())
+        .collect()
+}
+
+/// Checks `data`, a run of whole chunks except maybe the last one of the block, against their
+/// `checksums`.
+pub(super) fn verify(data: &[u8], checksums: &[u8]) -> bool {
+    chunks(data.len()) * CHECKSUM_SIZE == checksums.len()
+        && data
+            .chunks(CHUNK_SIZE)
+            .zip(checksums.chunks_exact(CHECKSUM_SIZE))
+            .all(|(chunk, checksum)| crc32c::crc32c(chunk).to_le_bytes() == checksum)
+}
@@ -0,0 +1,234 @@
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use tokio::sync::watch;
+
+use super::{Block, BlockId, BlockKey, DiskCache, INum};
+
+/// Outcome of a load shared with its waiters, `None` while the load runs.
+pub(super) type SharedLoad = Option<Result<Block, String>>;
+
+/// Removes a load from the loads in flight when it ends, even if the loading task is cancelled.
+struct InflightGuard<'a> {
+    inflight: &'a DashMap<BlockKey, watch::Receiver<SharedLoad>>,
+    key: BlockKey,
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.inflight.remove(&self.key);
+    }
+}
+
+impl DiskCache {
+    /// Gets the block data for the given inum and `BlockId`, loading it with `loader` and caching
+    /// it on a miss.
+    ///
+    /// Concurrent misses on the same block run a single `loader` and all get its result. A
+    /// loader error is returned to all of them but not cached, the next miss loads again. If the
+    /// task running the loader is cancelled, one of the waiters takes over.
+    ///
+    /// The admission filter records a single access to the block, hit or miss.
+    pub async fn get_or_load<F, Fut>(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        loader: F,
+    ) -> Result<Block>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Block>>,
+    {
+        let key = (inum, block_id);
+        self.record_access(&key);
+        loop {
+            if let Some(block) = self.get_block(inum, block_id).await? {
+                return Ok(block);
+            }
+            let mut receiver = match self.inflight.entry(key) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    let (sender, receiver) = watch::channel(None);
+                    entry.insert(receiver);
+                    return self.load(key, loader, sender).await;
+                }
+            };
+            let load = match receiver.wait_for(Option::is_some).await {
+                Ok(load) => load.clone(),
+                // The loading task was cancelled, try again.
+                Err(_) => continue,
+            };
+            match load {
+                Some(Ok(block)) => return Ok(block),
+                Some(Err(err)) => return Err(anyhow!("{}", err)),
+                None => {}
+            }
+        }
+    }
+
+    /// Loads a block missing from the cache, caches it and shares the outcome with the waiters.
+    async fn load<F, Fut>(
+        &self,
+        key: BlockKey,
+        loader: F,
+        sender: watch::Sender<SharedLoad>,
+    ) -> Result<Block>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Block>>,
+    {
+        let _guard = InflightGuard {
+            inflight: &self.inflight,
+            key,
+        };
+        let (inum, block_id) = key;
+        let result = async {
+            // The previous load of the block may have ended between the miss and now.
+            if let Some(block) = self.get_block(inum, block_id).await? {
+                return Ok(block);
+            }
+            let block = loader().await?;
+            self.set_block(inum, block_id, &block, true).await?;
+            Ok(block)
+        }
+        .await;
+        let shared = match &result {
+            Ok(block) => Ok(block.clone()),
+            Err(err) => Err(format!("{:#}", err)),
+        };
+        sender.send_replace(Some(shared));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::bail;
+
+    use super::*;
+    use crate::diskcache::{AdmissionPolicy, BLOCK_SIZE};
+
+    /// Test that concurrent misses run the loader once and share its result.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[allow(clippy::unwrap_used)]
+    async fn test_get_or_load() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = Arc::new(DiskCache::open(&tempdir).await.unwrap());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let disk_cache = Arc::clone(&disk_cache);
+                let loads = Arc::clone(&loads);
+                tokio::spawn(async move {
+                    disk_cache
+                        .get_or_load(1, 0, || async move {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok(Block::from(vec![1; 10]))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            let block = task.await.unwrap().unwrap();
+            assert_eq!(block.get_data(), &[1; 10]);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+        assert!(disk_cache.inflight.is_empty());
+    }
+
+    /// Test that a loader error reaches all the waiters and isn't cached.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[allow(clippy::unwrap_used)]
+    async fn test_get_or_load_error() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = Arc::new(DiskCache::open(&tempdir).await.unwrap());
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let disk_cache = Arc::clone(&disk_cache);
+                tokio::spawn(async move {
+                    disk_cache
+                        .get_or_load(1, 0, || async {
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            bail!("backing store unavailable")
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            let err = task.await.unwrap().err().unwrap();
+            assert!(err.to_string().contains("backing store unavailable"));
+        }
+        assert!(disk_cache.get(1, 0).await.unwrap().is_none());
+
+        let block = disk_cache
+            .get_or_load(1, 0, || async { Ok(Block::from(vec![2; 10])) })
+            .await
+            .unwrap();
+        assert_eq!(block.get_data(), &[2; 10]);
+    }
+
+    /// Test that a miss records a single access to the block in the admission filter.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_get_or_load_admission() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .admission_policy(AdmissionPolicy::TinyLfu)
+            .open()
+            .await
+            .unwrap();
+        let block = Block::from(vec![0; BLOCK_SIZE]);
+        disk_cache.set(1, 0, &block).await.unwrap();
+        disk_cache.set(1, 1, &block).await.unwrap();
+        assert!(disk_cache.get(1, 0).await.unwrap().is_some());
+
+        // Seen as often as block 1, block 2 is loaded but not cached.
+        let loaded = disk_cache
+            .get_or_load(1, 2, || async { Ok(Block::from(vec![2; BLOCK_SIZE])) })
+            .await
+            .unwrap();
+        assert_eq!(loaded.get_data(), &[2; BLOCK_SIZE]);
+        assert!(disk_cache.get_block(1, 2).await.unwrap().is_none());
+        assert!(disk_cache.get_block(1, 1).await.unwrap().is_some());
+    }
+
+    /// Test that a waiter takes over the load of a cancelled task.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_get_or_load_cancelled() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = Arc::new(DiskCache::open(&tempdir).await.unwrap());
+        let leader = {
+            let disk_cache = Arc::clone(&disk_cache);
+            tokio::spawn(async move {
+                disk_cache
+                    .get_or_load(1, 0, std::future::pending::<Result<Block>>)
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let waiter = {
+            let disk_cache = Arc::clone(&disk_cache);
+            tokio::spawn(async move {
+                disk_cache
+                    .get_or_load(1, 0, || async { Ok(Block::from(vec![3; 10])) })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        leader.abort();
+        let block = waiter.await.unwrap().unwrap();
+        assert_eq!(block.get_data(), &[3; 10]);
+    }
+}
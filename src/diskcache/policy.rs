@@ -0,0 +1,160 @@
+use std::hash::BuildHasher;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+
+use super::BlockId;
+use super::INum;
+use crate::admission::Admission;
+use crate::admission::TinyLfu;
+use crate::cache::Cache;
+use crate::fifo::Fifo;
+use crate::s3fifo::S3Fifo;
+use crate::FileSize;
+use crate::LruCache;
+
+/// `(INum, BlockId)` of a cached block.
+pub type BlockKey = (INum, BlockId);
+
+/// The in-memory policy choosing which blocks `DiskCache` evicts when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Evicts blocks in the order they were cached.
+    Fifo,
+    /// Evicts the least recently read block.
+    #[default]
+    Lru,
+    /// Evicts blocks with S3-FIFO, so a scan doesn't flush the blocks read more than once.
+    S3Fifo,
+}
+
+impl EvictionPolicy {
+    /// Creates an empty policy tracking blocks by their size on disk.
+    pub(super) fn build(self) -> Box<dyn BlockPolicy> {
+        // `DiskCache` decides when to evict, the policy only picks the victims.
+        let capacity = u64::MAX;
+        let hash_builder = DefaultHashBuilder::default();
+        match self {
+            EvictionPolicy::Fifo => Box::new(Fifo::<BlockKey, u64, _, _>::with_meter_and_hasher(
+                capacity,
+                FileSize,
+                hash_builder,
+            )),
+            EvictionPolicy::Lru => {
+                Box::new(LruCache::<BlockKey, u64, _, _>::with_meter_and_hasher(
+                    capacity,
+                    FileSize,
+                    hash_builder,
+                ))
+            }
+            EvictionPolicy::S3Fifo => {
+                Box::new(S3Fifo::<BlockKey, u64, _, _>::with_meter_and_hasher(
+                    capacity,
+                    FileSize,
+                    hash_builder,
+                ))
+            }
+        }
+    }
+}
+
+/// The filter deciding whether `DiskCache` caches a new block when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdmissionPolicy {
+    /// Caches every block, evicting the others as needed.
+    #[default]
+    Always,
+    /// Caches a new block only if it was accessed more often than the next block to evict, so
+    /// a scan of blocks read once doesn't flush the others.
+    TinyLfu,
+}
+
+impl AdmissionPolicy {
+    /// Creates the filter for a cache of about `blocks` blocks, `None` if every block is cached.
+    pub(super) fn build(self, blocks: usize) -> Option<BlockAdmission> {
+        match self {
+            AdmissionPolicy::Always => None,
+            AdmissionPolicy::TinyLfu => Some(BlockAdmission {
+                filter: Box::new(TinyLfu::new(blocks)),
+                hash_builder: DefaultHashBuilder::default(),
+            }),
+        }
+    }
+}
+
+/// An `Admission` filter over blocks.
+pub(super) struct BlockAdmission {
+    filter: Box<dyn Admission + Send>,
+    hash_builder: DefaultHashBuilder,
+}
+
+impl BlockAdmission {
+    /// Records an access to the block, cached or not.
+    pub(super) fn record(&mut self, key: &BlockKey) {
+        self.filter.record(self.hash_builder.hash_one(key));
+    }
+
+    /// Checks if the `candidate` block should be cached in place of the `victim` block.
+    pub(super) fn admit(&self, candidate: &BlockKey, victim: &BlockKey) -> bool {
+        self.filter.admit(
+            self.hash_builder.hash_one(candidate),
+            self.hash_builder.hash_one(victim),
+        )
+    }
+}
+
+/// The object safe subset of `Cache` `DiskCache` needs, the value of a block is its length.
+pub(super) trait BlockPolicy: Send {
+    /// Records a hit on the block.
+    fn touch(&mut self, key: &BlockKey);
+    /// Starts tracking the block, or updates its length.
+    fn insert(&mut self, key: BlockKey, len: u64);
+    /// Stops tracking the block, returns its length if it was tracked.
+    fn remove(&mut self, key: &BlockKey) -> Option<u64>;
+    /// Removes and returns the block to evict next.
+    fn evict(&mut self) -> Option<(BlockKey, u64)>;
+    /// Returns the block to evict next.
+    fn peek(&self) -> Option<BlockKey>;
+    /// Checks if the block is tracked.
+    fn contains(&self, key: &BlockKey) -> bool;
+    /// Returns the total length of the tracked blocks.
+    fn size(&self) -> u64;
+    /// Stops tracking every block.
+    fn clear(&mut self);
+}
+
+impl<C> BlockPolicy for C
+where
+    C: Cache<BlockKey, u64, DefaultHashBuilder, FileSize> + Send,
+{
+    fn touch(&mut self, key: &BlockKey) {
+        self.get(key);
+    }
+
+    fn insert(&mut self, key: BlockKey, len: u64) {
+        self.put(key, len);
+    }
+
+    fn remove(&mut self, key: &BlockKey) -> Option<u64> {
+        self.pop(key)
+    }
+
+    fn evict(&mut self) -> Option<(BlockKey, u64)> {
+        self.pop_by_policy()
+    }
+
+    fn peek(&self) -> Option<BlockKey> {
+        self.peek_by_policy().map(|(&key, _)| key)
+    }
+
+    fn contains(&self, key: &BlockKey) -> bool {
+        Cache::contains(self, key)
+    }
+
+    fn size(&self) -> u64 {
+        Cache::size(self)
+    }
+
+    fn clear(&mut self) {
+        Cache::clear(self);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use super::{Block, BlockId, DiskCache, INum};
+
+/// Default number of blocks read ahead once a sequential stream is detected.
+const DEFAULT_INITIAL_WINDOW: usize = 4;
+
+/// Default maximum number of blocks read ahead.
+const DEFAULT_MAX_WINDOW: usize = 64;
+
+/// Loads blocks missing from the cache from the backing store.
+pub trait Loader: Send + Sync + 'static {
+    /// Loads the block data for the given inum and `BlockId`.
+    fn load(&self, inum: INum, block_id: BlockId) -> impl Future<Output = Result<Block>> + Send;
+}
+
+/// How an inum has been read so far.
+struct Stream {
+    /// Block a sequential read would read next
+    next: BlockId,
+    /// Number of blocks to read ahead, 0 until the reads look sequential
+    window: usize,
+    /// Blocks before this one have already been read ahead
+    prefetched_until: BlockId,
+}
+
+/// Reads blocks through a `DiskCache`, and reads ahead the next blocks of the inums read
+/// sequentially.
+///
+/// The read-ahead window of an inum starts once a block is read right after the previous one,
+/// doubles on every further sequential read up to a maximum, and halves on every other read.
+/// The blocks read ahead are loaded in the background with `get_or_load`, so a read of a block
+/// being read ahead waits for it instead of loading it again.
+pub struct Prefetcher<L> {
+    /// Cache the blocks are read through
+    cache: Arc<DiskCache>,
+    /// Loads the missing blocks
+    loader: Arc<L>,
+    /// How every inum has been read so far
+    streams: Mutex<HashMap<INum, Stream>>,
+    /// Read-ahead window of a new sequential stream
+    initial_window: usize,
+    /// Maximum read-ahead window
+    max_window: usize,
+}
+
+impl<L: Loader> Prefetcher<L> {
+    /// Creates a prefetcher over `cache` loading the missing blocks with `loader`, reading ahead
+    /// 4 blocks of a new sequential stream and at most 64.
+    pub fn new(cache: Arc<DiskCache>, loader: L) -> Self {
+        Self::with_window(cache, loader, DEFAULT_INITIAL_WINDOW, DEFAULT_MAX_WINDOW)
+    }
+
+    /// Creates a prefetcher over `cache` loading the missing blocks with `loader`, reading ahead
+    /// `initial_window` blocks of a new sequential stream and at most `max_window`.
+    pub fn with_window(
+        cache: Arc<DiskCache>,
+        loader: L,
+        initial_window: usize,
+        max_window: usize,
+    ) -> Self {
+        Self {
+            cache,
+            loader: Arc::new(loader),
+            streams: Mutex::new(HashMap::new()),
+            initial_window: initial_window.min(max_window),
+            max_window,
+        }
+    }
+
+    /// Returns the cache the blocks are read through.
+    pub fn cache(&self) -> &Arc<DiskCache> {
+        &self.cache
+    }
+
+    /// Returns the current read-ahead window of the given inum.
+    pub fn window(&self, inum: INum) -> usize {
+        self.streams
+            .lock()
+            .get(&inum)
+            .map_or(0, |stream| stream.window)
+    }
+
+    /// Forgets how the given inum has been read, e.g. when its file is closed.
+    pub fn forget(&self, inum: INum) {
+        self.streams.lock().remove(&inum);
+    }
+
+    /// Gets the block data for the given inum and `BlockId`, loading it on a miss, and reads
+    /// ahead the next blocks if the inum is read sequentially.
+    pub async fn get(&self, inum: INum, block_id: BlockId) -> Result<Block> {
+        for ahead in self.record_read(inum, block_id) {
+            let cache = Arc::clone(&self.cache);
+            let loader = Arc::clone(&self.loader);
+            tokio::spawn(async move {
+                if cache.block_len(inum, ahead).await.is_none() {
+                    // Reading ahead is best effort, e.g. past the end of the file.
+                    let _ = cache
+                        .get_or_load(inum, ahead, || loader.load(inum, ahead))
+                        .await;
+                }
+            });
+        }
+        self.cache
+            .get_or_load(inum, block_id, || self.loader.load(inum, block_id))
+            .await
+    }
+
+    /// Updates the stream of the inum with a read of `block_id`, returns the blocks to read
+    /// ahead.
+    fn record_read(&self, inum: INum, block_id: BlockId) -> std::ops::Range<BlockId> {
+        let Some(next) = block_id.checked_add(1) else {
+            // The last block there can be, nothing comes after it.
+            return block_id..block_id;
+        };
+        let mut streams = self.streams.lock();
+        let Some(stream) = streams.get_mut(&inum) else {
+            streams.insert(
+                inum,
+                Stream {
+                    next,
+                    window: 0,
+                    prefetched_until: next,
+                },
+            );
+            return next..next;
+        };
+        if next == stream.next {
+            // Another read of the same block, e.g. a read smaller than a block.
+            return next..next;
+        }
+        if block_id == stream.next {
+            stream.window = match stream.window {
+                0 => self.initial_window,
+                window => window.saturating_mul(2).min(self.max_window),
+            };
+        } else {
+            stream.window /= 2;
+            stream.prefetched_until = next;
+        }
+        stream.next = next;
+        let start = stream.prefetched_until.max(next);
+        // The window stops at the last block there can be.
+        let end = next.saturating_add(stream.window as BlockId);
+        stream.prefetched_until = end.max(start);
+        start..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Loads blocks filled with their `BlockId` and counts the loads.
+    #[derive(Default)]
+    struct CountingLoader {
+        loads: AtomicUsize,
+    }
+
+    impl Loader for CountingLoader {
+        async fn load(&self, _: INum, block_id: BlockId) -> Result<Block> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            Ok(Block::from(vec![block_id as u8; 10]))
+        }
+    }
+
+    /// Test that sequential reads grow the window and random reads shrink it.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_prefetcher() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(DiskCache::open(&tempdir).await.unwrap());
+        let prefetcher = Prefetcher::with_window(cache, CountingLoader::default(), 2, 8);
+
+        let block = prefetcher.get(1, 0).await.unwrap();
+        assert_eq!(block.get_data(), &[0; 10]);
+        prefetcher.get(1, 0).await.unwrap();
+        assert_eq!(prefetcher.window(1), 0);
+        prefetcher.get(1, 1).await.unwrap();
+        assert_eq!(prefetcher.window(1), 2);
+        prefetcher.get(1, 2).await.unwrap();
+        assert_eq!(prefetcher.window(1), 4);
+        prefetcher.get(1, 3).await.unwrap();
+        assert_eq!(prefetcher.window(1), 8);
+        prefetcher.get(1, 4).await.unwrap();
+        assert_eq!(prefetcher.window(1), 8);
+        // Wait for the blocks read ahead in the background, failing only if they never land.
+        let read_ahead = async {
+            while prefetcher.cache().list_blocks(1).await.len() < 13 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), read_ahead)
+            .await
+            .unwrap();
+        assert_eq!(
+            prefetcher.cache().list_blocks(1).await,
+            (0..13).collect::<Vec<_>>()
+        );
+        // Every block was loaded once, read ahead or not.
+        assert_eq!(prefetcher.loader.loads.load(Ordering::SeqCst), 13);
+
+        prefetcher.get(1, 100).await.unwrap();
+        assert_eq!(prefetcher.window(1), 4);
+        prefetcher.get(1, 50).await.unwrap();
+        prefetcher.get(1, 20).await.unwrap();
+        prefetcher.get(1, 70).await.unwrap();
+        assert_eq!(prefetcher.window(1), 0);
+        assert_eq!(prefetcher.window(2), 0);
+        // The window stops at the last block there can be.
+        prefetcher.get(3, BlockId::MAX - 2).await.unwrap();
+        prefetcher.get(3, BlockId::MAX - 1).await.unwrap();
+        assert_eq!(prefetcher.window(3), 2);
+        prefetcher.get(3, BlockId::MAX).await.unwrap();
+        prefetcher.get(3, BlockId::MAX).await.unwrap();
+        prefetcher.forget(1);
+        prefetcher.get(1, 71).await.unwrap();
+        assert_eq!(prefetcher.window(1), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use tokio::fs::DirEntry;
+use tokio::sync::Mutex;
+
+use super::writeback::DIRTY_FILE;
+use super::{checksum, BlockId, BlockMap, DiskCache, INum};
+
+/// Parses the file name of the entry, `None` if it isn't valid UTF-8 or a `T`.
+fn parse_name<T: FromStr>(entry: &DirEntry) -> Option<T> {
+    entry.file_name().to_str()?.parse().ok()
+}
+
+/// Removes the entry, recursively if it is a directory.
+async fn remove_entry(entry: &DirEntry) -> Result<()> {
+    if entry.file_type().await?.is_dir() {
+        tokio::fs::remove_dir_all(entry.path()).await?;
+    } else {
+        tokio::fs::remove_file(entry.path()).await?;
+    }
+    Ok(())
+}
+
+impl DiskCache {
+    /// Rebuilds the block maps and the size of the cache from the blocks left on disk.
+    ///
+    /// The layout is `root/<inum>/<block_id>` next to the dirty file, anything else found under
+    /// the root path is removed, as well as the incomplete blocks. The recovered blocks enter the
+    /// eviction policy from the oldest to the newest, and the clean ones are evicted if they no
+    /// longer fit the capacity.
+    pub(super) async fn recover(&self) -> Result<()> {
+        self.load_dirty().await?;
+        let mut blocks = Vec::new();
+        let mut root_dir = tokio::fs::read_dir(&self.root_path).await?;
+        while let Some(inum_entry) = root_dir.next_entry().await? {
+            if inum_entry.file_name() == DIRTY_FILE {
+                continue;
+            }
+            let inum = match parse_name::<INum>(&inum_entry) {
+                Some(inum) if inum_entry.file_type().await?.is_dir() => inum,
+                _ => {
+                    remove_entry(&inum_entry).await?;
+                    continue;
+                }
+            };
+            let blocks_before = blocks.len();
+            self.recover_inum(&inum_entry.path(), inum, &mut blocks)
+                .await?;
+            if blocks.len() == blocks_before {
+                tokio::fs::remove_dir(inum_entry.path()).await?;
+            }
+        }
+        blocks.sort_unstable();
+        let recovered: HashSet<_> = blocks
+            .iter()
+            .map(|&(_, inum, block_id, _)| (inum, block_id))
+            .collect();
+        let lost_dirty = self.dirty.lock().retain(|key| recovered.contains(key));
+
+        let victims = {
+            let mut policy = self.policy.lock();
+            for (_, inum, block_id, len) in blocks {
+                self.map
+                    .entry(inum)
+                    .or_insert_with(|| Mutex::new(BlockMap::default()))
+                    .get_mut()
+                    .insert(block_id, len);
+                self.size
+                    .fetch_add(len, std::sync::atomic::Ordering::SeqCst);
+                policy.insert((inum, block_id), len as u64);
+            }
+            // If the dirty blocks alone don't fit, keep everything until they are flushed.
+            self.evict_clean(&mut **policy, 0).unwrap_or_default()
+        };
+        for (inum, block_id) in victims {
+            self.evict_block(inum, block_id).await?;
+        }
+        if lost_dirty {
+            self.persist_dirty().await?;
+        }
+        Ok(())
+    }
+
+    /// Collects the complete blocks of an inum directory as `(mtime, inum, block_id, len)` and
+    /// removes everything else.
+    async fn recover_inum(
+        &self,
+        inum_path: &Path,
+        inum: INum,
+        blocks: &mut Vec<(Option<SystemTime>, INum, BlockId, usize)>,
+    ) -> Result<()> {
+        let mut inum_dir = tokio::fs::read_dir(inum_path).await?;
+        while let Some(block_entry) = inum_dir.next_entry().await? {
+            let metadata = block_entry.metadata().await?;
+            let block_id = parse_name::<BlockId>(&block_entry);
+            match (block_id, self.complete_block_len(metadata.len())) {
+                (Some(block_id), Some(len)) if metadata.is_file() => {
+                    blocks.push((metadata.modified().ok(), inum, block_id, len));
+                }
+                _ => remove_entry(&block_entry).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the length of the block in a block file of `file_len` bytes, `None` if it can't
+    /// hold a whole block and its checksums. `set` never writes empty blocks.
+    ///
+    /// The checksums themselves are only verified when the block is read.
+    fn complete_block_len(&self, file_len: u64) -> Option<usize> {
+        let len = checksum::block_len(usize::try_from(file_len).ok()?)?;
+        (len <= self.block_size).then_some(len)
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use anyhow::{bail, Result};
+use tokio::io::AsyncWriteExt;
+
+use super::{Block, BlockId, BlockKey, DiskCache, INum, SyncMode};
+
+/// Name of the file under the cache root path listing the dirty blocks.
+pub(super) const DIRTY_FILE: &str = "dirty";
+
+/// Persists the dirty blocks of a `DiskCache` to the backing store, e.g. an object storage.
+pub trait Writeback: Send + Sync {
+    /// Writes the block data for the given inum and `BlockId` back to the backing store.
+    fn write_back(
+        &self,
+        inum: INum,
+        block_id: BlockId,
+        block: &Block,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// The blocks written to the cache but not to the backing store yet.
+#[derive(Default)]
+pub(super) struct DirtySet {
+    /// `(INum, BlockId)` -> Generation of the last write of the block
+    blocks: HashMap<BlockKey, u64>,
+    /// Generation of the next write
+    next_generation: u64,
+}
+
+impl DirtySet {
+    /// Marks the block dirty and returns the generation of this write.
+    fn mark(&mut self, key: BlockKey) -> u64 {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.blocks.insert(key, generation);
+        generation
+    }
+
+    /// Undoes the write of the given generation, unless the block was written again since. The
+    /// block gets back its `previous` generation if it was dirty before, and is clean otherwise.
+    fn unmark(&mut self, key: BlockKey, generation: u64, previous: Option<u64>) {
+        if self.blocks.get(&key) != Some(&generation) {
+            return;
+        }
+        match previous {
+            Some(previous) => self.blocks.insert(key, previous),
+            None => self.blocks.remove(&key),
+        };
+    }
+
+    /// Marks the block clean unless it was written again since the given generation.
+    fn clean(&mut self, key: &BlockKey, generation: u64) -> bool {
+        if self.blocks.get(key) == Some(&generation) {
+            self.blocks.remove(key);
+            return true;
+        }
+        false
+    }
+
+    /// Checks if the block is dirty.
+    pub(super) fn contains(&self, key: &BlockKey) -> bool {
+        self.blocks.contains_key(key)
+    }
+
+    /// Forgets the block, returns `true` if it was dirty.
+    pub(super) fn remove(&mut self, key: &BlockKey) -> bool {
+        self.blocks.remove(key).is_some()
+    }
+
+    /// Forgets the blocks `keep` returns `false` for, returns `true` if any was dirty.
+    pub(super) fn retain(&mut self, mut keep: impl FnMut(&BlockKey) -> bool) -> bool {
+        let len = self.blocks.len();
+        self.blocks.retain(|key, _| keep(key));
+        self.blocks.len() != len
+    }
+
+    /// Forgets all the blocks.
+    pub(super) fn clear(&mut self) {
+        self.blocks.clear();
+    }
+}
+
+impl DiskCache {
+    /// Sets the block data for the given inum and `BlockId` like `set`, and marks it dirty.
+    ///
+    /// A dirty block is never evicted until it is flushed to the backing store by `flush` or
+    /// `flush_all`. The dirty blocks are persisted before this returns, so they are still dirty
+    /// after a restart.
+    pub async fn set_dirty(&self, inum: INum, block_id: BlockId, block: &Block) -> Result<()> {
+        let key = (inum, block_id);
+        // Mark the block first, so it can't be evicted before it's dirty.
+        let (generation, previous) = {
+            let mut dirty = self.dirty.lock();
+            let previous = dirty.blocks.get(&key).copied();
+            (dirty.mark(key), previous)
+        };
+        if let Err(err) = self.set(inum, block_id, block).await {
+            // The unflushed write the block may hold is still on disk, it must stay dirty.
+            self.dirty.lock().unmark(key, generation, previous);
+            return Err(err);
+        }
+        self.persist_dirty().await
+    }
+
+    /// Marks a cached block dirty, e.g. after a `write_at`, returns `false` if it isn't cached.
+    pub async fn mark_dirty(&self, inum: INum, block_id: BlockId) -> Result<bool> {
+        {
+            let Some(file_cache_guard) = self.map.get(&inum) else {
+                return Ok(false);
+            };
+            let file_cache = file_cache_guard.lock().await;
+            if file_cache.get(&block_id).is_none() {
+                return Ok(false);
+            }
+            self.dirty.lock().mark((inum, block_id));
+        }
+        self.persist_dirty().await?;
+        Ok(true)
+    }
+
+    /// Checks if the block for the given inum and `BlockId` is dirty.
+    pub fn is_dirty(&self, inum: INum, block_id: BlockId) -> bool {
+        self.dirty.lock().contains(&(inum, block_id))
+    }
+
+    /// Returns the number of dirty blocks.
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.lock().blocks.len()
+    }
+
+    /// Writes the dirty blocks of the given inum back with `writeback`, in `BlockId` order, and
+    /// marks them clean.
+    ///
+    /// Stops at the first error, the blocks not written back yet stay dirty.
+    pub async fn flush<W: Writeback>(&self, inum: INum, writeback: &W) -> Result<()> {
+        self.flush_blocks(|key| key.0 == inum, writeback).await
+    }
+
+    /// Writes all the dirty blocks back with `writeback` and marks them clean.
+    ///
+    /// Stops at the first error, the blocks not written back yet stay dirty.
+    pub async fn flush_all<W: Writeback>(&self, writeback: &W) -> Result<()> {
+        self.flush_blocks(|_| true, writeback).await
+    }
+
+    /// Writes the dirty blocks selected by `filter` back and marks them clean.
+    async fn flush_blocks<W: Writeback>(
+        &self,
+        filter: impl Fn(&BlockKey) -> bool,
+        writeback: &W,
+    ) -> Result<()> {
+        let mut blocks: Vec<_> = self
+            .dirty
+            .lock()
+            .blocks
+            .iter()
+            .filter(|(key, _)| filter(key))
+            .map(|(&key, &generation)| (key, generation))
+            .collect();
+        blocks.sort_unstable();
+        let mut result = Ok(());
+        let mut cleaned = false;
+        for (key, generation) in blocks {
+            result = self.flush_block(key, writeback).await;
+            if result.is_err() {
+                break;
+            }
+            // A block written again while it was flushed stays dirty.
+            cleaned |= self.dirty.lock().clean(&key, generation);
+        }
+        if cleaned {
+            self.persist_dirty().await?;
+        }
+        result
+    }
+
+    /// Writes a dirty block back.
+    async fn flush_block<W: Writeback>(
+        &self,
+        (inum, block_id): BlockKey,
+        writeback: &W,
+    ) -> Result<()> {
+        let block = {
+            let Some(file_cache_guard) = self.map.get(&inum) else {
+                return Ok(());
+            };
+            let mut file_cache = file_cache_guard.lock().await;
+            let Some(&len) = file_cache.get(&block_id) else {
+                return Ok(());
+            };
+            match self.read_verified(inum, block_id, len, 0, len).await? {
+                Some(data) => Block::from(data),
+                None => {
+                    self.discard_corrupted(&mut file_cache, inum, block_id)
+                        .await?;
+                    bail!(
+                        "dirty block {} of inum {} is corrupted and was lost",
+                        block_id,
+                        inum
+                    );
+                }
+            }
+        };
+        writeback.write_back(inum, block_id, &block).await
+    }
+
+    /// Writes the dirty set to the dirty file.
+    pub(super) async fn persist_dirty(&self) -> Result<()> {
+        // Persist one at a time, the last one to write the file holds the latest dirty set.
+        let _guard = self.dirty_persist.lock().await;
+        let content = {
+            let dirty = self.dirty.lock();
+            let mut content = String::new();
+            for (inum, block_id) in dirty.blocks.keys() {
+                content.push_str(&format!("{inum} {block_id}\n"));
+            }
+            content
+        };
+        let path = self.root_path.join(DIRTY_FILE);
+        let tmp_path = self.root_path.join(format!("{DIRTY_FILE}.tmp"));
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(content.as_bytes()).await?;
+        if self.sync_mode != SyncMode::Never {
+            file.sync_all().await?;
+        }
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    /// Reads the dirty set back from the dirty file, if any.
+    pub(super) async fn load_dirty(&self) -> Result<()> {
+        let path = self.root_path.join(DIRTY_FILE);
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut dirty = self.dirty.lock();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace().map(str::parse);
+            if let (Some(Ok(inum)), Some(Ok(block_id)), None) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                dirty.mark((inum, block_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::diskcache::{path_of_tmp_block, BLOCK_SIZE};
+
+    /// Records the blocks written back, or fails.
+    #[derive(Default)]
+    struct Recorder {
+        written: Mutex<Vec<(INum, BlockId, Vec<u8>)>>,
+        fail: bool,
+    }
+
+    impl Writeback for Recorder {
+        async fn write_back(&self, inum: INum, block_id: BlockId, block: &Block) -> Result<()> {
+            if self.fail {
+                bail!("backing store unavailable");
+            }
+            let data = block.get_data().to_vec();
+            self.written.lock().push((inum, block_id, data));
+            Ok(())
+        }
+    }
+
+    /// Test that dirty blocks aren't evicted until they are flushed.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_writeback() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .open()
+            .await
+            .unwrap();
+        for block_id in [1, 0] {
+            let block = Block::from(vec![block_id as u8; BLOCK_SIZE]);
+            disk_cache.set_dirty(1, block_id, &block).await.unwrap();
+        }
+        assert_eq!(disk_cache.dirty_count(), 2);
+        let block = Block::from(vec![2; BLOCK_SIZE]);
+        assert!(disk_cache.set(1, 2, &block).await.is_err());
+
+        let failing = Recorder {
+            fail: true,
+            ..Default::default()
+        };
+        assert!(disk_cache.flush(1, &failing).await.is_err());
+        assert!(disk_cache.is_dirty(1, 0));
+
+        let recorder = Recorder::default();
+        disk_cache.flush(2, &recorder).await.unwrap();
+        assert!(recorder.written.lock().is_empty());
+        disk_cache.flush(1, &recorder).await.unwrap();
+        let written = std::mem::take(&mut *recorder.written.lock());
+        assert_eq!(
+            written,
+            vec![(1, 0, vec![0; BLOCK_SIZE]), (1, 1, vec![1; BLOCK_SIZE])]
+        );
+        assert_eq!(disk_cache.dirty_count(), 0);
+        disk_cache.set(1, 2, &block).await.unwrap();
+        assert_eq!(disk_cache.list_blocks(1).await, vec![0, 2]);
+
+        // Removed blocks are no longer dirty.
+        assert!(disk_cache.mark_dirty(1, 0).await.unwrap());
+        assert!(!disk_cache.mark_dirty(1, 1).await.unwrap());
+        disk_cache.remove_block(1, 0).await.unwrap();
+        assert!(!disk_cache.is_dirty(1, 0));
+    }
+
+    /// Test that a failed `set_dirty` keeps the earlier write of the block dirty.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_writeback_failed_set() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        let block = Block::from(vec![1; BLOCK_SIZE]);
+        disk_cache.set_dirty(1, 0, &block).await.unwrap();
+        // The block can't be rewritten while a directory is in the way of its temporary file.
+        let tmp_path = path_of_tmp_block(tempdir.path(), 1, 0);
+        std::fs::create_dir(&tmp_path).unwrap();
+        let block = Block::from(vec![2; BLOCK_SIZE]);
+        assert!(disk_cache.set_dirty(1, 0, &block).await.is_err());
+        assert!(disk_cache.set_dirty(1, 1, &block).await.is_ok());
+        assert!(disk_cache.is_dirty(1, 0));
+
+        let recorder = Recorder::default();
+        disk_cache.flush(1, &recorder).await.unwrap();
+        let written = std::mem::take(&mut *recorder.written.lock());
+        assert_eq!(
+            written,
+            vec![(1, 0, vec![1; BLOCK_SIZE]), (1, 1, vec![2; BLOCK_SIZE])]
+        );
+        assert_eq!(disk_cache.dirty_count(), 0);
+
+        // A block that wasn't dirty before is clean again.
+        assert!(disk_cache.set_dirty(1, 0, &block).await.is_err());
+        assert!(!disk_cache.is_dirty(1, 0));
+    }
+
+    /// Test that the dirty blocks are still dirty after a restart.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_writeback_recover() {
+        let tempdir = tempfile::tempdir().unwrap();
+        {
+            let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+            let block = Block::from(vec![1; BLOCK_SIZE]);
+            disk_cache.set_dirty(1, 0, &block).await.unwrap();
+            disk_cache.set_dirty(1, 1, &block).await.unwrap();
+            disk_cache.set(2, 0, &block).await.unwrap();
+        }
+        // A dirty block lost while the cache was closed is forgotten.
+        std::fs::remove_file(tempdir.path().join("1").join("1")).unwrap();
+
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        assert!(disk_cache.is_dirty(1, 0));
+        assert!(!disk_cache.is_dirty(2, 0));
+        assert_eq!(disk_cache.dirty_count(), 1);
+        let recorder = Recorder::default();
+        disk_cache.flush_all(&recorder).await.unwrap();
+        assert_eq!(recorder.written.lock().len(), 1);
+
+        drop(disk_cache);
+        let disk_cache = DiskCache::open(&tempdir).await.unwrap();
+        assert_eq!(disk_cache.dirty_count(), 0);
+        assert_eq!(disk_cache.size(), 2 * BLOCK_SIZE);
+    }
+}
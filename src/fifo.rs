@@ -1,56 +1,174 @@
-use crate::BasicCache;
-use crossbeam_queue::ArrayQueue;
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
 use std::hash::Hash;
-use std::rc::Rc;
 
-pub struct Fifo<K: Eq + Hash, V> {
-    queue: ArrayQueue<Rc<K>>,
-    table: hashbrown::HashMap<Rc<K>, V>,
-    max_capacity: usize,
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+/// A FIFO cache: items are evicted in insertion order, hits never reorder them.
+pub struct Fifo<
+    K: Eq + Hash,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    M: CountableMeter<K, V> = Count,
+> {
+    /// Items in insertion order, the oldest one is at the front.
+    map: LinkedHashMap<K, V, S>,
+    current_measure: M::Measure,
+    max_capacity: u64,
+    meter: M,
 }
 
 impl<K: Eq + Hash, V> Fifo<K, V> {
-    pub fn new(capacity: usize) -> Self {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            map: LinkedHashMap::new(),
+            current_measure: (),
+            max_capacity: capacity,
+            meter: Count,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, M: CountableMeter<K, V>> Fifo<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
         Self {
-            queue: ArrayQueue::new(capacity),
-            table: hashbrown::HashMap::with_capacity(capacity),
+            map: LinkedHashMap::new(),
+            current_measure: Default::default(),
             max_capacity: capacity,
+            meter,
         }
     }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for Fifo<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            map: LinkedHashMap::with_hasher(hash_builder),
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    /// A hit doesn't change the eviction order, so this is the same as `peek`.
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(k)
+    }
 
-    pub fn put(&mut self, key: K, value: V) -> Option<V> {
-        let key_rc = Rc::new(key);
-        if self.table.len() == self.max_capacity && !self.table.contains_key(&key_rc) {
-            // Evict only if new key and cache is full
-            self.evict();
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(k)
+    }
+
+    /// The oldest item is at the front of the map.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.map.front()
+    }
+
+    /// Updating an existing key keeps its position in the queue.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_size = self.meter.measure(&k, &v);
+        if self.meter.size(new_size).unwrap_or(1) > self.max_capacity {
+            // Larger than the whole queue: it isn't queued and nothing is evicted for it, but the
+            // value it replaces is stale and dropped all the same.
+            return self.pop(&k);
+        }
+        self.current_measure = self.meter.add(self.current_measure, new_size);
+        if let Some(old) = self.map.get(&k) {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, old));
         }
-        self.queue.push(key_rc.clone()).ok()?;
-        self.table.insert(key_rc, value)
+        let old_val = self.map.replace(k, v);
+        while self.size() > self.capacity() {
+            self.pop_by_policy();
+        }
+        old_val
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(k).inspect(|v| {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(k, v));
+        })
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        self.map.pop_front().map(|(k, v)| {
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, &v));
+            (k, v)
+        })
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
-        self.table.get(key)
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
     }
 
-    fn evict(&mut self) {
-        if let Some(key_rc) = self.queue.pop() {
-            self.table.remove(&key_rc);
-        } else {
-            panic!("Queue is empty, but eviction was attempted");
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
         }
+        self.max_capacity = capacity;
+    }
+
+    fn size(&self) -> u64 {
+        self.meter
+            .size(self.current_measure)
+            .unwrap_or_else(|| self.map.len() as u64)
     }
 
-    pub fn len(&self) -> usize {
-        self.table.len()
+    fn clear(&mut self) {
+        self.map.clear();
+        self.current_measure = Default::default();
     }
 }
 
 impl<K: Eq + Hash, V> BasicCache<K, V> for Fifo<K, V> {
     fn get_basic(&mut self, key: &K) -> Option<&V> {
-        Fifo::get(self, key)
+        Cache::get(self, key)
     }
 
-    fn put_basic(&mut self, key: K, value: V) -> () {
-        Fifo::put(self, key, value);
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
     }
 }
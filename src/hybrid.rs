@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use hashbrown::hash_map::DefaultHashBuilder;
+use parking_lot::Mutex;
+
+use crate::cache::Cache;
+use crate::diskcache::{Block, BlockId, BlockKey, DiskCache, INum};
+use crate::BytesMeter;
+use crate::LruCache;
+
+/// The default memory tier of a `HybridCache`, an LRU cache bounded by the bytes of its blocks.
+pub type MemoryTier = LruCache<BlockKey, Arc<Vec<u8>>, DefaultHashBuilder, BytesMeter>;
+
+/// A two-tier cache of blocks: the hot blocks in memory, the others in a `DiskCache`.
+///
+/// The memory tier is any `Cache` policy bounded by the bytes of its blocks. The blocks it
+/// evicts are demoted to the disk tier, and the blocks found on disk are promoted back to
+/// memory, so a block lives in one tier at a time.
+pub struct HybridCache<P = MemoryTier> {
+    /// Memory tier
+    memory: Mutex<P>,
+    /// Disk tier
+    disk: DiskCache,
+}
+
+impl<P> HybridCache<P>
+where
+    P: Cache<BlockKey, Arc<Vec<u8>>, DefaultHashBuilder, BytesMeter>,
+{
+    /// Creates a cache holding at most `memory_capacity` bytes of blocks in memory on top of
+    /// `disk`.
+    pub fn new(memory_capacity: u64, disk: DiskCache) -> Self {
+        let memory =
+            P::with_meter_and_hasher(memory_capacity, BytesMeter, DefaultHashBuilder::default());
+        Self {
+            memory: Mutex::new(memory),
+            disk,
+        }
+    }
+
+    /// Returns the disk tier.
+    pub fn disk(&self) -> &DiskCache {
+        &self.disk
+    }
+
+    /// Returns the size of the blocks in memory.
+    pub fn memory_size(&self) -> u64 {
+        self.memory.lock().size()
+    }
+
+    /// Returns the capacity of the memory tier.
+    pub fn memory_capacity(&self) -> u64 {
+        self.memory.lock().capacity()
+    }
+
+    /// Inserts a block in the memory tier, and demotes the blocks it evicts to the disk tier one
+    /// at a time.
+    ///
+    /// A `promoted` block comes from the disk tier, it isn't inserted if the key was set in
+    /// memory meanwhile, since it's stale then.
+    async fn insert_in_memory(
+        &self,
+        key: BlockKey,
+        data: Arc<Vec<u8>>,
+        promoted: bool,
+    ) -> Result<()> {
+        let len = data.len() as u64;
+        if len > self.memory_capacity() {
+            // The block can never be hot, keep it on disk.
+            let data = Arc::unwrap_or_clone(data);
+            return self.disk.set(key.0, key.1, &Block::from(data)).await;
+        }
+        loop {
+            let demoted = {
+                let mut memory = self.memory.lock();
+                // Checked under the same lock hold as the insert, so a concurrent `set` either
+                // lands first and is kept, or lands after and replaces the promoted block.
+                if promoted && memory.contains(&key) {
+                    return Ok(());
+                }
+                memory.pop(&key);
+                // Evict by hand, `put` would drop the blocks instead of handing them over.
+                let demoted = if memory.size() + len > memory.capacity() {
+                    memory.pop_by_policy()
+                } else {
+                    None
+                };
+                match demoted {
+                    Some(demoted) => demoted,
+                    None => {
+                        memory.put(key, data);
+                        return Ok(());
+                    }
+                }
+            };
+            self.demote(demoted).await?;
+        }
+    }
+
+    /// Writes a block evicted from the memory tier to the disk tier. If that fails, the block is
+    /// put back in memory if it still fits, so it isn't lost from both tiers.
+    async fn demote(&self, (key, data): (BlockKey, Arc<Vec<u8>>)) -> Result<()> {
+        let block = Block::from(Arc::unwrap_or_clone(data));
+        // The filter of the disk tier must not drop a block that only lives here now.
+        let Err(err) = self.disk.set_unfiltered(key.0, key.1, &block).await else {
+            return Ok(());
+        };
+        let mut memory = self.memory.lock();
+        let len = block.get_data().len() as u64;
+        if !memory.contains(&key) && memory.size() + len <= memory.capacity() {
+            memory.put(key, Arc::new(block.get_data().to_vec()));
+        }
+        Err(err)
+    }
+
+    /// Sets the block data for the given inum and `BlockId` in the memory tier, replacing the
+    /// cached block if any.
+    pub async fn set(&self, inum: INum, block_id: BlockId, block: &Block) -> Result<()> {
+        // Drop the stale copy first, so it can't be promoted over the new block.
+        self.disk.remove_block(inum, block_id).await?;
+        let data = Arc::new(block.get_data().to_vec());
+        self.insert_in_memory((inum, block_id), data, false).await
+    }
+
+    /// Gets the block data for the given inum and `BlockId`.
+    ///
+    /// A block found on disk is promoted to the memory tier.
+    pub async fn get(&self, inum: INum, block_id: BlockId) -> Result<Option<Block>> {
+        let key = (inum, block_id);
+        if let Some(data) = self.memory.lock().get(&key) {
+            return Ok(Some(Block::from(data.to_vec())));
+        }
+        let Some(block) = self.disk.get(inum, block_id).await? else {
+            return Ok(None);
+        };
+        if let Some(data) = self.memory.lock().get(&key) {
+            // Set while reading the disk, the disk copy is stale.
+            return Ok(Some(Block::from(data.to_vec())));
+        }
+        if block.get_data().len() as u64 > self.memory_capacity() {
+            return Ok(Some(block));
+        }
+        self.disk.remove_block(inum, block_id).await?;
+        let data = Arc::new(block.get_data().to_vec());
+        self.insert_in_memory(key, data, true).await?;
+        Ok(Some(block))
+    }
+
+    /// Checks if the block for the given inum and `BlockId` is in the memory tier.
+    pub fn in_memory(&self, inum: INum, block_id: BlockId) -> bool {
+        self.memory.lock().contains(&(inum, block_id))
+    }
+
+    /// Removes the block data for the given inum and `BlockId` from both tiers.
+    pub async fn remove_block(&self, inum: INum, block_id: BlockId) -> Result<()> {
+        self.memory.lock().pop(&(inum, block_id));
+        self.disk.remove_block(inum, block_id).await
+    }
+
+    /// Clears both tiers.
+    pub async fn clear(&self) -> Result<()> {
+        self.memory.lock().clear();
+        self.disk.clear().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diskcache::{AdmissionPolicy, BLOCK_SIZE};
+    use crate::s3fifo::S3Fifo;
+
+    /// Test that memory evictions are demoted to disk and disk hits promoted back.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_hybrid_cache() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk = DiskCache::open(&tempdir).await.unwrap();
+        let cache = HybridCache::<MemoryTier>::new(2 * BLOCK_SIZE as u64, disk);
+        for block_id in 0..3 {
+            let block = Block::from(vec![block_id as u8; BLOCK_SIZE]);
+            cache.set(1, block_id, &block).await.unwrap();
+        }
+        assert_eq!(cache.memory_size(), 2 * BLOCK_SIZE as u64);
+        assert!(!cache.in_memory(1, 0));
+        assert_eq!(cache.disk().list_blocks(1).await, vec![0]);
+
+        let read = cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[0; BLOCK_SIZE]);
+        assert!(cache.in_memory(1, 0));
+        assert!(!cache.in_memory(1, 1));
+        assert_eq!(cache.disk().list_blocks(1).await, vec![1]);
+
+        // A new version replaces the demoted block.
+        cache.set(1, 1, &Block::from(vec![9; 10])).await.unwrap();
+        assert_eq!(cache.disk().list_blocks(1).await, vec![2]);
+        let read = cache.get(1, 1).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[9; 10]);
+
+        cache.remove_block(1, 2).await.unwrap();
+        assert!(cache.get(1, 2).await.unwrap().is_none());
+        cache.clear().await.unwrap();
+        assert_eq!(cache.memory_size(), 0);
+        assert!(cache.get(1, 0).await.unwrap().is_none());
+    }
+
+    /// Test a memory tier with another policy, and blocks too large for it.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_hybrid_cache_s3fifo() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk = DiskCache::open(&tempdir).await.unwrap();
+        let cache = HybridCache::<S3Fifo<_, _, _, _>>::new(BLOCK_SIZE as u64 / 2, disk);
+        cache.set(1, 0, &Block::from(vec![1; 100])).await.unwrap();
+        assert!(cache.in_memory(1, 0));
+        cache
+            .set(1, 1, &Block::from(vec![2; BLOCK_SIZE]))
+            .await
+            .unwrap();
+        assert!(!cache.in_memory(1, 1));
+        assert_eq!(cache.disk().list_blocks(1).await, vec![1]);
+        let read = cache.get(1, 1).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[2; BLOCK_SIZE]);
+        assert!(cache.in_memory(1, 0));
+        assert_eq!(cache.disk().list_blocks(1).await, vec![1]);
+    }
+
+    /// Test that a stale block read from disk isn't promoted over a block set meanwhile.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_hybrid_cache_stale_promotion() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk = DiskCache::open(&tempdir).await.unwrap();
+        let cache = HybridCache::<MemoryTier>::new(2 * BLOCK_SIZE as u64, disk);
+        cache.set(1, 0, &Block::from(vec![2; 10])).await.unwrap();
+        // The promotion of the disk copy read before that `set` comes last.
+        let stale = Arc::new(vec![1; 10]);
+        cache.insert_in_memory((1, 0), stale, true).await.unwrap();
+        let read = cache.get(1, 0).await.unwrap().unwrap();
+        assert_eq!(read.get_data(), &[2; 10]);
+    }
+
+    /// Test that the admission filter of the disk tier doesn't drop the demoted blocks.
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_hybrid_cache_demote_admission() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let disk = DiskCache::builder(&tempdir)
+            .capacity(2 * BLOCK_SIZE)
+            .admission_policy(AdmissionPolicy::TinyLfu)
+            .open()
+            .await
+            .unwrap();
+        let cache = HybridCache::<MemoryTier>::new(BLOCK_SIZE as u64, disk);
+        for block_id in 0..4 {
+            let block = Block::from(vec![block_id as u8; BLOCK_SIZE]);
+            cache.set(1, block_id, &block).await.unwrap();
+        }
+        // Seen once like the blocks on disk, the filter would have rejected block 2.
+        let mut on_disk = cache.disk().list_blocks(1).await;
+        on_disk.sort();
+        assert_eq!(on_disk, vec![1, 2]);
+        assert!(cache.in_memory(1, 3));
+    }
+}
@@ -0,0 +1,445 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashbrown::HashMap;
+use hashbrown::HashTable;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+/// An item, linked to the other items of its frequency from the least to the most recently used.
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// Number of accesses, halved on every aging.
+    freq: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// The items of a frequency, linked to the buckets of the nearest lower and higher frequencies.
+struct Bucket {
+    /// The least recently used item.
+    head: usize,
+    /// The most recently used item.
+    tail: usize,
+    prev: Option<u64>,
+    next: Option<u64>,
+}
+
+/// An LFU cache: the least frequently used item is evicted first, the least recently used one
+/// on a tie.
+///
+/// The items of every frequency are kept in a bucket ordered by recency, and the buckets in a
+/// list ordered by frequency, so hits, inserts and evictions are all O(1). A new item has been
+/// accessed once, so room is made for it before it's inserted.
+///
+/// Frequencies only grow by default, so items that were hot long ago are never evicted. With an
+/// aging period, see [`Lfu::set_aging_period`], every frequency is halved after that many hits.
+pub struct Lfu<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// Indexes of the items in `nodes`, by the hash of their key.
+    table: HashTable<usize>,
+    nodes: Vec<Node<K, V>>,
+    /// Frequency -> its bucket.
+    buckets: HashMap<u64, Bucket>,
+    /// The lowest frequency, evictions start from its bucket.
+    min_freq: Option<u64>,
+    aging_period: Option<u64>,
+    /// Hits since the frequencies were last halved.
+    hits: u64,
+    hash_builder: S,
+    current_measure: M::Measure,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> Lfu<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> Lfu<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Lfu<K, V, S, M> {
+    /// Returns the number of hits after which every frequency is halved, `None` if they never
+    /// are.
+    pub fn aging_period(&self) -> Option<u64> {
+        self.aging_period
+    }
+
+    /// Halves every frequency after every `period` hits, or never if `None`.
+    ///
+    /// Aging is O(n), a period of at least the number of items keeps hits O(1) amortized.
+    pub fn set_aging_period(&mut self, period: Option<u64>) {
+        assert!(period != Some(0), "aging period must not be 0");
+        self.aging_period = period;
+        self.hits = 0;
+    }
+
+    /// Returns the frequency of the given key, the number of times it was accessed since it was
+    /// inserted, halved on every aging.
+    pub fn frequency<Q>(&self, k: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).map(|i| self.nodes[i].freq)
+    }
+
+    fn find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.table
+            .find(hash, |&i| self.nodes[i].key.borrow() == k)
+            .copied()
+    }
+
+    /// Returns the index of the item the next eviction picks.
+    fn victim(&self) -> Option<usize> {
+        self.min_freq
+            .and_then(|freq| self.buckets.get(&freq))
+            .map(|bucket| bucket.head)
+    }
+
+    /// Records a hit on the item at `i`, moving it to the bucket of the next frequency.
+    fn touch(&mut self, i: usize) {
+        let freq = self.nodes[i].freq;
+        let lower = self.buckets.get(&freq).and_then(|bucket| bucket.prev);
+        let after = if self.unlink(i) { lower } else { Some(freq) };
+        self.nodes[i].freq = freq.saturating_add(1);
+        self.link(i, after);
+
+        if let Some(period) = self.aging_period {
+            self.hits += 1;
+            if self.hits >= period {
+                self.hits = 0;
+                self.age();
+            }
+        }
+    }
+
+    /// Unlinks the item at `i` from its bucket, returns `true` if the bucket was left empty and
+    /// removed.
+    fn unlink(&mut self, i: usize) -> bool {
+        let Node {
+            freq, prev, next, ..
+        } = self.nodes[i];
+        if let Some(prev) = prev {
+            self.nodes[prev].next = next;
+        }
+        if let Some(next) = next {
+            self.nodes[next].prev = prev;
+        }
+        let Some(bucket) = self.buckets.get_mut(&freq) else {
+            return false;
+        };
+        match (prev, next) {
+            (None, None) => {
+                let (lower, higher) = (bucket.prev, bucket.next);
+                self.buckets.remove(&freq);
+                match lower.and_then(|lower| self.buckets.get_mut(&lower)) {
+                    Some(bucket) => bucket.next = higher,
+                    None => self.min_freq = higher,
+                }
+                if let Some(bucket) = higher.and_then(|higher| self.buckets.get_mut(&higher)) {
+                    bucket.prev = lower;
+                }
+                return true;
+            }
+            (None, Some(next)) => bucket.head = next,
+            (Some(prev), None) => bucket.tail = prev,
+            (Some(_), Some(_)) => {}
+        }
+        false
+    }
+
+    /// Links the item at `i` as the most recently used one of the bucket of its frequency. A
+    /// missing bucket is created right after the bucket of the `after` frequency, or first if
+    /// `None`.
+    fn link(&mut self, i: usize, after: Option<u64>) {
+        let freq = self.nodes[i].freq;
+        self.nodes[i].next = None;
+        if let Some(bucket) = self.buckets.get_mut(&freq) {
+            let tail = std::mem::replace(&mut bucket.tail, i);
+            self.nodes[tail].next = Some(i);
+            self.nodes[i].prev = Some(tail);
+            return;
+        }
+        self.nodes[i].prev = None;
+        let higher = match after {
+            Some(lower) => self
+                .buckets
+                .get_mut(&lower)
+                .and_then(|bucket| bucket.next.replace(freq)),
+            None => self.min_freq.replace(freq),
+        };
+        if let Some(bucket) = higher.and_then(|higher| self.buckets.get_mut(&higher)) {
+            bucket.prev = Some(freq);
+        }
+        self.buckets.insert(
+            freq,
+            Bucket {
+                head: i,
+                tail: i,
+                prev: after,
+                next: higher,
+            },
+        );
+    }
+
+    /// Halves every frequency, merging the buckets that end up with the same one.
+    fn age(&mut self) {
+        let mut buckets: HashMap<u64, Bucket> = HashMap::with_capacity(self.buckets.len());
+        let mut last: Option<u64> = None;
+        let mut next = self.min_freq;
+        while let Some(freq) = next {
+            let Some(bucket) = self.buckets.remove(&freq) else {
+                break;
+            };
+            next = bucket.next;
+            let aged = Ord::max(freq / 2, 1);
+            let mut item = Some(bucket.head);
+            while let Some(i) = item {
+                self.nodes[i].freq = aged;
+                item = self.nodes[i].next;
+            }
+            // Frequencies stay in the same order, so only neighbours can merge. The items of the
+            // lower one stay less recently used.
+            if last == Some(aged) {
+                if let Some(merged) = buckets.get_mut(&aged) {
+                    self.nodes[merged.tail].next = Some(bucket.head);
+                    self.nodes[bucket.head].prev = Some(merged.tail);
+                    merged.tail = bucket.tail;
+                }
+                continue;
+            }
+            if let Some(lower) = last.and_then(|lower| buckets.get_mut(&lower)) {
+                lower.next = Some(aged);
+            }
+            buckets.insert(
+                aged,
+                Bucket {
+                    head: bucket.head,
+                    tail: bucket.tail,
+                    prev: last,
+                    next: None,
+                },
+            );
+            last = Some(aged);
+        }
+        self.min_freq = self.min_freq.map(|freq| Ord::max(freq / 2, 1));
+        self.buckets = buckets;
+    }
+
+    /// Inserts a new item with a frequency of 1.
+    fn insert_new(&mut self, k: K, v: V) {
+        let hash = self.hash_builder.hash_one(&k);
+        let i = self.nodes.len();
+        self.nodes.push(Node {
+            key: k,
+            value: v,
+            freq: 1,
+            prev: None,
+            next: None,
+        });
+        let (nodes, hash_builder) = (&self.nodes, &self.hash_builder);
+        self.table
+            .insert_unique(hash, i, |&j| hash_builder.hash_one(&nodes[j].key));
+        // No frequency is lower than 1.
+        self.link(i, None);
+    }
+
+    /// Removes the item at `i` and returns it.
+    fn remove_at(&mut self, i: usize) -> (K, V) {
+        self.unlink(i);
+        let hash = self.hash_builder.hash_one(&self.nodes[i].key);
+        if let Ok(entry) = self.table.find_entry(hash, |&j| j == i) {
+            entry.remove();
+        }
+        let node = self.nodes.swap_remove(i);
+        if i < self.nodes.len() {
+            // The last item was moved to `i`, fix the links to it.
+            let moved = self.nodes.len();
+            let Node {
+                freq, prev, next, ..
+            } = self.nodes[i];
+            if let Some(prev) = prev {
+                self.nodes[prev].next = Some(i);
+            }
+            if let Some(next) = next {
+                self.nodes[next].prev = Some(i);
+            }
+            if let Some(bucket) = self.buckets.get_mut(&freq) {
+                if bucket.head == moved {
+                    bucket.head = i;
+                }
+                if bucket.tail == moved {
+                    bucket.tail = i;
+                }
+            }
+            let hash = self.hash_builder.hash_one(&self.nodes[i].key);
+            if let Some(index) = self.table.find_mut(hash, |&j| j == moved) {
+                *index = i;
+            }
+        }
+        self.current_measure = self.meter.sub(
+            self.current_measure,
+            self.meter.measure(&node.key, &node.value),
+        );
+        (node.key, node.value)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for Lfu<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            table: HashTable::new(),
+            nodes: Vec::new(),
+            buckets: HashMap::new(),
+            min_freq: None,
+            aging_period: None,
+            hits: 0,
+            hash_builder,
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = self.find(k)?;
+        self.touch(i);
+        Some(&self.nodes[i].value)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).map(|i| &self.nodes[i].value)
+    }
+
+    /// The least recently used item of the lowest frequency.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.victim()
+            .map(|i| (&self.nodes[i].key, &self.nodes[i].value))
+    }
+
+    /// Updating an existing key counts as a hit.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        let new_size = self.meter.size(new_measure).unwrap_or(1);
+        if new_size > self.max_capacity {
+            // It could never fit, so no frequency bucket is emptied for it. The value it replaces
+            // is dropped along with its count.
+            return self.pop(&k);
+        }
+        if let Some(i) = self.find(&k) {
+            let old = std::mem::replace(&mut self.nodes[i].value, v);
+            self.current_measure = self.meter.add(self.current_measure, new_measure);
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, &old));
+            self.touch(i);
+            while self.size() > self.capacity() {
+                self.pop_by_policy();
+            }
+            return Some(old);
+        }
+        while self.size() + new_size > self.capacity() {
+            if self.pop_by_policy().is_none() {
+                break;
+            }
+        }
+        self.current_measure = self.meter.add(self.current_measure, new_measure);
+        self.insert_new(k, v);
+        None
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = self.find(k)?;
+        Some(self.remove_at(i).1)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        let i = self.victim()?;
+        Some(self.remove_at(i))
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).is_some()
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.max_capacity = capacity;
+    }
+
+    fn size(&self) -> u64 {
+        self.meter
+            .size(self.current_measure)
+            .unwrap_or(self.nodes.len() as u64)
+    }
+
+    fn clear(&mut self) {
+        self.table.clear();
+        self.nodes.clear();
+        self.buckets.clear();
+        self.min_freq = None;
+        self.hits = 0;
+        self.current_measure = Default::default();
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for Lfu<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
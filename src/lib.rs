@@ -17,31 +17,41 @@
 
 mod meter;
 
-mod s3fifo;
-
+pub mod admission;
+pub mod arc;
+pub mod cache;
+pub mod clock;
 pub mod diskcache;
 pub mod fifo;
-
+pub mod hybrid;
+pub mod lfu;
+pub mod s3fifo;
+pub mod sharded;
+pub mod sieve;
+pub mod wtinylfu;
+
+pub use cache::lru::LruCache;
+pub use cache::Cache;
 pub use hashbrown::hash_map::DefaultHashBuilder;
-use hashlink::LruCache;
 pub use meter::bytes_meter::BytesMeter;
 pub use meter::count_meter::Count;
 pub use meter::count_meter::CountableMeter;
 pub use meter::count_meter::CountableMeterWithMeasure;
 pub use meter::file_meter::FileSize;
 pub use meter::Meter;
+pub use sharded::ShardedCache;
 
 pub trait BasicCache<K, V> {
     fn get_basic(&mut self, key: &K) -> Option<&V>;
     fn put_basic(&mut self, key: K, value: V);
 }
 
-impl BasicCache<i32, i32> for LruCache<i32, i32> {
+impl BasicCache<i32, i32> for hashlink::LruCache<i32, i32> {
     fn get_basic(&mut self, key: &i32) -> Option<&i32> {
-        LruCache::get(self, key)
+        hashlink::LruCache::get(self, key)
     }
 
     fn put_basic(&mut self, key: i32, value: i32) {
-        LruCache::insert(self, key, value);
+        hashlink::LruCache::insert(self, key, value);
     }
 }
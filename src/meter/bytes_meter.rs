@@ -18,6 +18,7 @@ use std::sync::Arc;
 use bytes::Bytes;
 
 use super::Meter;
+#[derive(Clone, Copy)]
 pub struct BytesMeter;
 
 impl<K> Meter<K, Vec<u8>> for BytesMeter {
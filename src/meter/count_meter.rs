@@ -17,6 +17,7 @@ use std::borrow::Borrow;
 use super::Meter;
 
 /// Size limit based on a simple count of cache items.
+#[derive(Clone, Copy)]
 pub struct Count;
 
 impl<K, V> Meter<K, V> for Count {
@@ -16,6 +16,7 @@ use std::borrow::Borrow;
 
 use super::Meter;
 
+#[derive(Clone, Copy)]
 pub struct FileSize;
 
 /// Given a tuple of (path, filesize), use the filesize for measurement.
@@ -23,6 +23,7 @@ use super::Meter;
 /// Requires cache entries that implement [`HeapSizeOf`][1].
 ///
 /// [1]: https://doc.servo.org/heapsize/trait.HeapSizeOf.html
+#[derive(Clone, Copy)]
 pub struct HeapSize;
 
 impl<K, V: HeapSizeOf> Meter<K, V> for HeapSize {
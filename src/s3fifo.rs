@@ -1,115 +1,451 @@
+mod concurrent;
+
 use std::borrow::Borrow;
-use std::collections::VecDeque;
+use std::hash::BuildHasher;
 use std::hash::Hash;
 use std::sync::atomic::AtomicU8;
 use std::sync::atomic::Ordering::SeqCst;
 
-struct Item<K, V> {
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+pub use self::concurrent::ConcurrentS3Fifo;
+
+struct Item<V> {
     freq: AtomicU8,
-    key: K,
     value: V,
 }
 
-impl<K, V> Item<K, V> {
-    fn new(key: K, value: V) -> Self {
+impl<V> Item<V> {
+    fn new(value: V) -> Self {
         Self {
             freq: AtomicU8::default(),
-            key,
             value,
         }
     }
-}
 
-enum CacheState {
-    Small,
-    Main,
-    Ghost,
+    /// Records a hit, the frequency saturates at 3.
+    fn hit(&self) {
+        let inc = |freq: u8| Some(Ord::min(freq + 1, 3));
+        // If this fails, this might pushed into the `ghost` queue too soon.
+        let _ = self.freq.fetch_update(SeqCst, SeqCst, inc);
+    }
+
+    /// Decrements the frequency and returns the previous one.
+    fn decrement(&self) -> u8 {
+        let dec = |freq: u8| Some(freq.saturating_sub(1));
+        match self.freq.fetch_update(SeqCst, SeqCst, dec) {
+            Ok(prev) => prev,
+            // If the decrement failed, the item stays in the `main` queue for one more round,
+            // this should be okay if it happens rarely.
+            Err(prev) => prev,
+        }
+    }
 }
-pub struct S3Fifo<K, V> {
-    small: VecDeque<Item<K, V>>,
-    main: VecDeque<Item<K, V>>,
-    ghost: VecDeque<K>,
-    table : hashbrown::HashMap<K, CacheState>,
+
+/// An S3-FIFO cache, see <https://dl.acm.org/doi/10.1145/3600006.3613147>.
+///
+/// New items enter the `small` queue, items accessed more than once while in `small` are moved
+/// to the `main` queue and the others are evicted, leaving their key in the `ghost` queue so a
+/// quick re-insert goes straight to `main`.
+///
+/// The capacity is measured by the `Meter`, the `small` queue gets 10% of it. Every queue is
+/// indexed by a hash table, so lookups, ghost hits and evictions are all O(1).
+///
+/// The `ghost` queue only remembers the keys of items whose total size is within a fraction of
+/// the `main` queue's capacity, 90% by default, see [`S3Fifo::set_ghost_ratio`].
+pub struct S3Fifo<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// The oldest item is at the front.
+    small: LinkedHashMap<K, Item<V>, S>,
+    /// The oldest item is at the front.
+    main: LinkedHashMap<K, Item<V>, S>,
+    /// Hashes of the keys evicted from `small` and the size of their items.
+    ghost: LinkedHashMap<u64, u64, S>,
+    small_measure: M::Measure,
+    main_measure: M::Measure,
+    ghost_size: u64,
+    ghost_ratio: f64,
+    max_capacity: u64,
+    meter: M,
 }
 
 impl<K: Hash + Eq, V> S3Fifo<K, V> {
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            small: VecDeque::with_capacity(capacity / 10),
-            main: VecDeque::with_capacity(capacity / 10 * 9),
-            ghost: VecDeque::with_capacity(capacity / 10 * 9),
-            table : hashbrown::HashMap::with_capacity(capacity),
-        }
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn with_capacity(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> S3Fifo<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> S3Fifo<K, V, S, M> {
+    /// Inserts a key-value pair into the cache. If the key already existed, its value is updated
+    /// in place and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where S: Clone {
+        Cache::put(self, key, value)
+    }
+
+    /// Removes the given key from the cache and returns its corresponding value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        S: Clone,
+    {
+        Cache::pop(self, key)
     }
 
+    /// Returns the size of the `ghost` queue as a fraction of the `main` queue's capacity.
+    pub fn ghost_ratio(&self) -> f64 {
+        self.ghost_ratio
+    }
+
+    /// Sets the size of the `ghost` queue as a fraction of the `main` queue's capacity.
+    pub fn set_ghost_ratio(&mut self, ratio: f64) {
+        assert!(ratio >= 0.0, "ghost ratio must not be negative");
+        self.ghost_ratio = ratio;
+        self.shrink_ghost();
+    }
+
+    /// Returns a reference to the value of the given key and records the hit.
+    ///
+    /// Only the atomic frequency of the item is updated, so this doesn't need exclusive access.
     pub fn get<Q>(&self, key: &Q) -> Option<&V>
-        where
-            K: Borrow<Q>,
-            Q: Hash + Eq + ?Sized,
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let item = self.find(key)?;
+        item.hit();
+        Some(&item.value)
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<&Item<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
     {
         // If the cache is in either the `main` or `small` queue, we'll find it there.
-        let item = match self.small.iter().find(|item| item.key.borrow() == key) {
-            Some(item) => item,
-            None => match self.main.iter().find(|item| item.key.borrow() == key) {
-                Some(item) => item,
-                None => return None,
-            },
-        };
+        self.small.get(key).or_else(|| self.main.get(key))
+    }
 
-        let inc = |freq: u8| Some(Ord::min(freq + 1, 3));
-        // If this fails, this might pushed into the `ghost` queue too soon.
-        let _ = item.freq.fetch_update(SeqCst, SeqCst, inc);
-        Some(&item.value)
+    /// Returns the size of `measure`, or `count` if the meter doesn't measure anything.
+    fn size_of(&self, measure: M::Measure, count: usize) -> u64 {
+        self.meter.size(measure).unwrap_or(count as u64)
+    }
+
+    /// The `small` queue may hold 10% of the capacity.
+    fn small_capacity(&self) -> u64 {
+        Ord::max(self.max_capacity / 10, 1)
     }
 
-    pub fn insert(&mut self, key: K, value: V) {
-        if self.small.len() == self.small.capacity() {
-            self.evict_small();
+    fn small_is_full(&self) -> bool {
+        self.size_of(self.small_measure, self.small.len()) > self.small_capacity()
+    }
+
+    fn ghost_capacity(&self) -> u64 {
+        let main_capacity = self.max_capacity.saturating_sub(self.small_capacity());
+        (main_capacity as f64 * self.ghost_ratio) as u64
+    }
+
+    /// Forgets the oldest keys of the `ghost` queue until it fits its capacity.
+    fn shrink_ghost(&mut self) {
+        let capacity = self.ghost_capacity();
+        while self.ghost_size > capacity {
+            match self.ghost.pop_front() {
+                Some((_, size)) => self.ghost_size -= size,
+                None => break,
+            }
         }
+    }
 
-        // Does the new entry take its `freq` from the ghost queue?
-        match self.ghost.contains(&key) {
-            true => &mut self.main,
-            false => &mut self.small,
+    fn hash_of<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+    {
+        self.ghost.hasher().hash_one(key)
+    }
+
+    fn evict(&mut self) -> Option<(K, Item<V>)> {
+        if self.small_is_full() || self.main.is_empty() {
+            if let Some(evicted) = self.evict_small() {
+                return Some(evicted);
+            }
         }
-            .push_front(Item::new(key, value));
+        self.evict_main()
     }
 
-    fn evict_small(&mut self) {
-        while let Some(tail) = self.small.pop_back() {
+    fn evict_small(&mut self) -> Option<(K, Item<V>)> {
+        while let Some((key, tail)) = self.small.pop_front() {
+            let measure = self.meter.measure(&key, &tail.value);
+            self.small_measure = self.meter.sub(self.small_measure, measure);
             if tail.freq.load(SeqCst) > 1 {
-                if self.main.len() == self.main.capacity() {
-                    self.evict_main();
-                }
-
-                self.main.push_front(tail);
+                self.main_measure = self.meter.add(self.main_measure, measure);
+                self.main.insert(key, tail);
                 continue;
             }
 
-            self.ghost.push_front(tail.key);
-            break;
+            let hash = self.hash_of(&key);
+            let size = self.size_of(measure, 1);
+            if let Some(old_size) = self.ghost.insert(hash, size) {
+                self.ghost_size -= old_size;
+            }
+            self.ghost_size += size;
+            self.shrink_ghost();
+            return Some((key, tail));
         }
+        None
     }
 
-    fn evict_main(&mut self) {
-        while let Some(tail) = self.main.pop_back() {
-            let dec = |freq: u8| Some(freq.saturating_sub(1));
-            let freq = match tail.freq.fetch_update(SeqCst, SeqCst, dec) {
-                Ok(prev) => prev,
-                // If the decrement failed, we'll insert this at the head of the queue for one
-                // more round, this should be okay if it happens rarely.
-                Err(prev) => prev,
-            };
-
-            if freq > 0 {
-                self.main.push_front(tail);
+    fn evict_main(&mut self) -> Option<(K, Item<V>)> {
+        while let Some((key, tail)) = self.main.pop_front() {
+            if tail.decrement() > 0 {
+                self.main.insert(key, tail);
             } else {
-                break;
+                let measure = self.meter.measure(&key, &tail.value);
+                self.main_measure = self.meter.sub(self.main_measure, measure);
+                return Some((key, tail));
             }
         }
+        None
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for S3Fifo<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            small: LinkedHashMap::with_hasher(hash_builder.clone()),
+            main: LinkedHashMap::with_hasher(hash_builder.clone()),
+            ghost: LinkedHashMap::with_hasher(hash_builder),
+            small_measure: Default::default(),
+            main_measure: Default::default(),
+            ghost_size: 0,
+            ghost_ratio: 0.9,
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        S3Fifo::get(self, k)
     }
 
-    pub fn len(&self) -> usize {
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).map(|item| &item.value)
+    }
+
+    /// Returns the tail of the queue the next eviction starts from, the item itself may still be
+    /// given another chance.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        let queue = if self.small_is_full() || self.main.is_empty() {
+            &self.small
+        } else {
+            &self.main
+        };
+        queue.front().map(|(key, item)| (key, &item.value))
+    }
+
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        let new_size = self.size_of(new_measure, 1);
+        if new_size > self.max_capacity {
+            // Larger than both queues together, so neither is flushed for it. A cached older
+            // value of the key is stale now and dropped.
+            return self.pop(&k);
+        }
+
+        if let Some(item) = self.small.get_mut(&k) {
+            let old = std::mem::replace(&mut item.value, v);
+            let old_measure = self.meter.measure(&k, &old);
+            self.small_measure = self.meter.add(self.small_measure, new_measure);
+            self.small_measure = self.meter.sub(self.small_measure, old_measure);
+            while self.size() > self.capacity() {
+                self.pop_by_policy();
+            }
+            return Some(old);
+        }
+        if let Some(item) = self.main.get_mut(&k) {
+            let old = std::mem::replace(&mut item.value, v);
+            let old_measure = self.meter.measure(&k, &old);
+            self.main_measure = self.meter.add(self.main_measure, new_measure);
+            self.main_measure = self.meter.sub(self.main_measure, old_measure);
+            while self.size() > self.capacity() {
+                self.pop_by_policy();
+            }
+            return Some(old);
+        }
+
+        // Does the new entry take its `freq` from the ghost queue? Items larger than the whole
+        // `small` queue would be evicted right away, so they go to `main` too.
+        let hash = self.hash_of(&k);
+        let ghost_hit = match self.ghost.remove(&hash) {
+            Some(size) => {
+                self.ghost_size -= size;
+                true
+            }
+            None => false,
+        };
+        if ghost_hit || new_size > self.small_capacity() {
+            self.main_measure = self.meter.add(self.main_measure, new_measure);
+            self.main.insert(k, Item::new(v));
+        } else {
+            self.small_measure = self.meter.add(self.small_measure, new_measure);
+            self.small.insert(k, Item::new(v));
+        }
+
+        while self.size() > self.capacity() {
+            self.pop_by_policy();
+        }
+        None
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(item) = self.small.remove(k) {
+            let measure = self.meter.measure(k, &item.value);
+            self.small_measure = self.meter.sub(self.small_measure, measure);
+            return Some(item.value);
+        }
+        let item = self.main.remove(k)?;
+        let measure = self.meter.measure(k, &item.value);
+        self.main_measure = self.meter.sub(self.main_measure, measure);
+        Some(item.value)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        self.evict().map(|(key, item)| (key, item.value))
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.small.contains_key(k) || self.main.contains_key(k)
+    }
+
+    fn len(&self) -> usize {
         self.small.len() + self.main.len()
     }
+
+    fn is_empty(&self) -> bool {
+        self.small.is_empty() && self.main.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        self.max_capacity = capacity;
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.shrink_ghost();
+    }
+
+    fn size(&self) -> u64 {
+        let measure = self.meter.add(self.small_measure, self.main_measure);
+        self.size_of(measure, self.len())
+    }
+
+    fn clear(&mut self) {
+        self.small.clear();
+        self.main.clear();
+        self.ghost.clear();
+        self.ghost_size = 0;
+        self.small_measure = Default::default();
+        self.main_measure = Default::default();
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for S3Fifo<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        S3Fifo::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BytesMeter;
+
+    #[test]
+    fn test_bytes_meter() {
+        let mut cache = S3Fifo::with_meter(100, BytesMeter);
+        for i in 0..10 {
+            cache.put(i, vec![0u8; 10]);
+        }
+        assert_eq!(cache.size(), 100);
+        assert_eq!(cache.small.len(), 10);
+
+        // Hit `0` twice so it's promoted to `main` instead of being evicted.
+        cache.get(&0);
+        cache.get(&0);
+        cache.put(10, vec![0u8; 10]);
+        assert_eq!(cache.size(), 100);
+        assert!(cache.contains(&0));
+        assert!(!cache.contains(&1));
+        assert_eq!(cache.main.len(), 1);
+
+        // Larger than the `small` queue, goes straight to `main`.
+        cache.put(11, vec![0u8; 20]);
+        assert_eq!(cache.main.back().map(|(key, _)| *key), Some(11));
+        assert!(cache.size() <= 100);
+
+        // Larger than the whole cache, never inserted.
+        cache.put(12, vec![0u8; 101]);
+        assert!(!cache.contains(&12));
+        assert!(cache.size() <= 100);
+    }
+
+    #[test]
+    fn test_bounded_ghost() {
+        let mut cache = S3Fifo::with_capacity(100);
+        cache.set_ghost_ratio(0.5);
+        for i in 0..1000 {
+            cache.put(i, i);
+        }
+        // The `main` queue may hold 90 items, the ghost queue half of that.
+        assert_eq!(cache.ghost.len(), 45);
+        assert_eq!(cache.ghost_size, 45);
+        assert_eq!(cache.len(), 100);
+
+        // `899` was evicted recently, it's inserted straight into `main`.
+        cache.put(899, 0);
+        assert!(cache.main.contains_key(&899));
+
+        cache.set_ghost_ratio(0.0);
+        assert!(cache.ghost.is_empty());
+        cache.put(898, 0);
+        assert!(cache.small.contains_key(&898));
+    }
 }
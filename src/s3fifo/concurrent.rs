@@ -0,0 +1,368 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering::SeqCst;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+use parking_lot::Mutex;
+
+use super::Item;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+
+/// The items of a `ConcurrentS3Fifo`, readable without a lock.
+type Items<K, V, S> = papaya::HashMap<K, Item<V>, S>;
+
+/// A thread-safe S3-FIFO cache with a lock-free read path.
+///
+/// The items live in a concurrent hash table whose readers are protected by epochs, so `get`
+/// finds an item and bumps its atomic frequency without taking any lock, and never waits for a
+/// writer. Only inserts, removals and the evictions they trigger lock the queues, which keep the
+/// keys in eviction order like [`S3Fifo`](super::S3Fifo) does.
+///
+/// Values are cloned out of the cache, a replaced or removed item is freed once no reader can
+/// see it anymore.
+pub struct ConcurrentS3Fifo<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    items: Items<K, V, S>,
+    queues: Mutex<Queues<K, V, S, M>>,
+}
+
+/// The `small`, `main` and `ghost` queues of a `ConcurrentS3Fifo`.
+struct Queues<K, V, S, M: CountableMeter<K, V>> {
+    /// Keys of the items in `small` and their measure, the oldest is at the front.
+    small: LinkedHashMap<K, M::Measure, S>,
+    /// Keys of the items in `main` and their measure, the oldest is at the front.
+    main: LinkedHashMap<K, M::Measure, S>,
+    /// Hashes of the keys evicted from `small` and the size of their items.
+    ghost: LinkedHashMap<u64, u64, S>,
+    small_measure: M::Measure,
+    main_measure: M::Measure,
+    ghost_size: u64,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ConcurrentS3Fifo<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn with_capacity(capacity: u64) -> Self {
+        Self::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, M: CountableMeter<K, V>>
+    ConcurrentS3Fifo<K, V, DefaultHashBuilder, M>
+{
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Self::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Clone, M: CountableMeter<K, V>>
+    ConcurrentS3Fifo<K, V, S, M>
+{
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter` with the
+    /// given hash builder.
+    pub fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            items: papaya::HashMap::with_hasher(hash_builder.clone()),
+            queues: Mutex::new(Queues {
+                small: LinkedHashMap::with_hasher(hash_builder.clone()),
+                main: LinkedHashMap::with_hasher(hash_builder.clone()),
+                ghost: LinkedHashMap::with_hasher(hash_builder),
+                small_measure: Default::default(),
+                main_measure: Default::default(),
+                ghost_size: 0,
+                max_capacity: capacity,
+                meter,
+            }),
+        }
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, and records the hit.
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let items = self.items.pin();
+        let item = items.get(k)?;
+        item.hit();
+        Some(item.value.clone())
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, without recording the
+    /// hit.
+    pub fn peek<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.items.pin().get(k).map(|item| item.value.clone())
+    }
+
+    /// Inserts a key-value pair into the cache. If the key already existed, the old value is
+    /// returned.
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        self.queues.lock().put(&self.items, k, v)
+    }
+
+    /// Removes the given key from the cache and returns its corresponding value.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.queues.lock().pop(&self.items, k)
+    }
+
+    /// Checks if the cache contains the given key.
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.items.pin().contains_key(k)
+    }
+
+    /// Returns the number of key-value pairs in the cache.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the cache contains no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the maximum size of the key-value pairs the cache can hold, as measured by the
+    /// `Meter` used by the cache.
+    pub fn capacity(&self) -> u64 {
+        self.queues.lock().max_capacity
+    }
+
+    /// Sets the size of the key-value pairs the cache can hold, as measured by the `Meter` used by
+    /// the cache.
+    pub fn set_capacity(&self, capacity: u64) {
+        let mut queues = self.queues.lock();
+        queues.max_capacity = capacity;
+        queues.evict_to_fit(&self.items);
+        queues.shrink_ghost();
+    }
+
+    /// Returns the size of all the key-value pairs in the cache, as measured by the `Meter` used
+    /// by the cache.
+    pub fn size(&self) -> u64 {
+        self.queues.lock().size()
+    }
+
+    /// Removes all key-value pairs from the cache.
+    pub fn clear(&self) {
+        let mut queues = self.queues.lock();
+        queues.small.clear();
+        queues.main.clear();
+        queues.ghost.clear();
+        queues.ghost_size = 0;
+        queues.small_measure = Default::default();
+        queues.main_measure = Default::default();
+        self.items.pin().clear();
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Clone, M: CountableMeter<K, V>>
+    Queues<K, V, S, M>
+{
+    /// Returns the size of `measure`, or `count` if the meter doesn't measure anything.
+    fn size_of(&self, measure: M::Measure, count: usize) -> u64 {
+        self.meter.size(measure).unwrap_or(count as u64)
+    }
+
+    fn size(&self) -> u64 {
+        let measure = self.meter.add(self.small_measure, self.main_measure);
+        self.size_of(measure, self.small.len() + self.main.len())
+    }
+
+    /// The `small` queue may hold 10% of the capacity.
+    fn small_capacity(&self) -> u64 {
+        Ord::max(self.max_capacity / 10, 1)
+    }
+
+    fn small_is_full(&self) -> bool {
+        self.size_of(self.small_measure, self.small.len()) > self.small_capacity()
+    }
+
+    /// The `ghost` queue remembers items of up to 90% of the `main` queue's capacity, the
+    /// default of `S3Fifo`.
+    fn ghost_capacity(&self) -> u64 {
+        let main_capacity = self.max_capacity.saturating_sub(self.small_capacity());
+        (main_capacity as f64 * 0.9) as u64
+    }
+
+    /// Forgets the oldest keys of the `ghost` queue until it fits its capacity.
+    fn shrink_ghost(&mut self) {
+        let capacity = self.ghost_capacity();
+        while self.ghost_size > capacity {
+            match self.ghost.pop_front() {
+                Some((_, size)) => self.ghost_size -= size,
+                None => break,
+            }
+        }
+    }
+
+    fn put(&mut self, items: &Items<K, V, S>, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        let new_size = self.size_of(new_measure, 1);
+        if new_size > self.max_capacity {
+            // Larger than both queues together, so neither is flushed for it. A cached older
+            // value of the key is stale now and dropped.
+            return self.pop(items, &k);
+        }
+
+        let updated = if let Some(measure) = self.small.get_mut(&k) {
+            Some((
+                &mut self.small_measure,
+                std::mem::replace(measure, new_measure),
+            ))
+        } else if let Some(measure) = self.main.get_mut(&k) {
+            Some((
+                &mut self.main_measure,
+                std::mem::replace(measure, new_measure),
+            ))
+        } else {
+            None
+        };
+        let old = if let Some((queue_measure, old_measure)) = updated {
+            *queue_measure = self.meter.add(*queue_measure, new_measure);
+            *queue_measure = self.meter.sub(*queue_measure, old_measure);
+            // The new item takes the frequency of the old one, a hit recorded in between is
+            // lost.
+            let items = items.pin();
+            let freq = items.get(&k).map_or(0, |item| item.freq.load(SeqCst));
+            let item = Item {
+                freq: AtomicU8::new(freq),
+                value: v,
+            };
+            items.insert(k, item).map(|old| old.value.clone())
+        } else {
+            // Does the new entry take its `freq` from the ghost queue? Items larger than the
+            // whole `small` queue would be evicted right away, so they go to `main` too.
+            let hash = self.ghost.hasher().hash_one(&k);
+            let ghost_hit = match self.ghost.remove(&hash) {
+                Some(size) => {
+                    self.ghost_size -= size;
+                    true
+                }
+                None => false,
+            };
+            if ghost_hit || new_size > self.small_capacity() {
+                self.main_measure = self.meter.add(self.main_measure, new_measure);
+                self.main.insert(k.clone(), new_measure);
+            } else {
+                self.small_measure = self.meter.add(self.small_measure, new_measure);
+                self.small.insert(k.clone(), new_measure);
+            }
+            items.pin().insert(k, Item::new(v));
+            None
+        };
+
+        self.evict_to_fit(items);
+        old
+    }
+
+    fn pop<Q>(&mut self, items: &Items<K, V, S>, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(measure) = self.small.remove(k) {
+            self.small_measure = self.meter.sub(self.small_measure, measure);
+        } else {
+            let measure = self.main.remove(k)?;
+            self.main_measure = self.meter.sub(self.main_measure, measure);
+        }
+        items.pin().remove(k).map(|item| item.value.clone())
+    }
+
+    /// Evicts items until the rest fit the capacity.
+    fn evict_to_fit(&mut self, items: &Items<K, V, S>) {
+        while self.size() > self.max_capacity {
+            if !self.evict(items) {
+                break;
+            }
+        }
+    }
+
+    /// Evicts an item, returns `false` if there is none.
+    fn evict(&mut self, items: &Items<K, V, S>) -> bool {
+        if (self.small_is_full() || self.main.is_empty()) && self.evict_small(items) {
+            return true;
+        }
+        self.evict_main(items)
+    }
+
+    fn evict_small(&mut self, items: &Items<K, V, S>) -> bool {
+        let items = items.pin();
+        while let Some((key, measure)) = self.small.pop_front() {
+            self.small_measure = self.meter.sub(self.small_measure, measure);
+            let freq = items.get(&key).map_or(0, |item| item.freq.load(SeqCst));
+            if freq > 1 {
+                self.main_measure = self.meter.add(self.main_measure, measure);
+                self.main.insert(key, measure);
+                continue;
+            }
+
+            let hash = self.ghost.hasher().hash_one(&key);
+            let size = self.size_of(measure, 1);
+            if let Some(old_size) = self.ghost.insert(hash, size) {
+                self.ghost_size -= old_size;
+            }
+            self.ghost_size += size;
+            self.shrink_ghost();
+            items.remove(&key);
+            return true;
+        }
+        false
+    }
+
+    fn evict_main(&mut self, items: &Items<K, V, S>) -> bool {
+        let items = items.pin();
+        while let Some((key, measure)) = self.main.pop_front() {
+            if items.get(&key).is_some_and(|item| item.decrement() > 0) {
+                self.main.insert(key, measure);
+            } else {
+                self.main_measure = self.meter.sub(self.main_measure, measure);
+                items.remove(&key);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Test that a reader doesn't wait for a writer holding the queues.
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_get_while_locked() {
+        let cache = ConcurrentS3Fifo::with_capacity(10);
+        cache.insert(1, 10);
+        let queues = cache.queues.lock();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                assert_eq!(cache.get(&1), Some(10));
+                assert!(cache.contains(&1));
+                assert_eq!(cache.len(), 1);
+            });
+        });
+        drop(queues);
+        let freq = cache.items.pin().get(&1).unwrap().freq.load(SeqCst);
+        assert_eq!(freq, 1);
+    }
+}
@@ -0,0 +1,172 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use parking_lot::Mutex;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::Meter;
+
+/// A thread-safe cache that hashes keys across independently locked shards of a `Cache` policy.
+///
+/// Every method takes `&self`, so the cache can be shared between threads or Tokio tasks behind
+/// an `Arc`. The capacity is split evenly between the shards, and each shard evicts on its own.
+pub struct ShardedCache<K, V, P, S = DefaultHashBuilder, M = Count> {
+    shards: Box<[Mutex<P>]>,
+    hash_builder: S,
+    _marker: PhantomData<fn(K, V, M)>,
+}
+
+impl<K, V, P> ShardedCache<K, V, P>
+where
+    K: Eq + Hash,
+    P: Cache<K, V, DefaultHashBuilder, Count>,
+{
+    /// Creates an empty cache of `shards` shards holding at most `capacity` items in total.
+    pub fn new(shards: usize, capacity: u64) -> Self {
+        Self::with_meter_and_hasher(shards, capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, P, S, M> ShardedCache<K, V, P, S, M>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+    M: Meter<K, V> + Clone,
+    P: Cache<K, V, S, M>,
+{
+    /// Creates an empty cache of `shards` shards that can hold at most `capacity` as measured by
+    /// `meter` in total.
+    ///
+    /// There are only as many shards as the capacity if it's smaller, so none is left unable to
+    /// hold anything.
+    pub fn with_meter_and_hasher(shards: usize, capacity: u64, meter: M, hash_builder: S) -> Self {
+        assert!(shards > 0, "a sharded cache needs at least one shard");
+        let shards = Ord::max(Ord::min(shards as u64, capacity), 1) as usize;
+        let shards = (0..shards)
+            .map(|idx| {
+                let shard_capacity = Self::shard_capacity(capacity, shards, idx);
+                Mutex::new(P::with_meter_and_hasher(
+                    shard_capacity,
+                    meter.clone(),
+                    hash_builder.clone(),
+                ))
+            })
+            .collect();
+        Self {
+            shards,
+            hash_builder,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the capacity of the shard at `idx`, the remainder goes to the first shards. Every
+    /// shard can hold at least 1.
+    fn shard_capacity(capacity: u64, shards: usize, idx: usize) -> u64 {
+        let shards = shards as u64;
+        let shard_capacity = capacity / shards + u64::from((idx as u64) < capacity % shards);
+        Ord::max(shard_capacity, 1)
+    }
+
+    fn shard<Q>(&self, k: &Q) -> &Mutex<P>
+    where Q: Hash + ?Sized {
+        // The shards hash with the same builder and index their tables with the low bits, so
+        // pick the shard with the high bits to keep every shard's table evenly spread.
+        let hash = self.hash_builder.hash_one(k);
+        &self.shards[(hash >> 32) as usize % self.shards.len()]
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, and records the hit
+    /// in the shard's policy.
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.shard(k).lock().get(k).cloned()
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, without updating the
+    /// shard's policy.
+    pub fn peek<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.shard(k).lock().peek(k).cloned()
+    }
+
+    /// Inserts a key-value pair into the cache. If the key already existed, the old value is
+    /// returned.
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        self.shard(&k).lock().put(k, v)
+    }
+
+    /// Removes the given key from the cache and returns its corresponding value.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(k).lock().pop(k)
+    }
+
+    /// Checks if the cache contains the given key.
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(k).lock().contains(k)
+    }
+
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the number of key-value pairs in the cache.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    /// Returns `true` if the cache contains no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    /// Returns the maximum size of the key-value pairs the cache can hold, as measured by the
+    /// `Meter` used by the cache.
+    pub fn capacity(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.lock().capacity()).sum()
+    }
+
+    /// Sets the size of the key-value pairs the cache can hold, as measured by the `Meter` used by
+    /// the cache.
+    ///
+    /// The number of shards is fixed, so a capacity smaller than it is rounded up to 1 per shard.
+    pub fn set_capacity(&self, capacity: u64) {
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let shard_capacity = Self::shard_capacity(capacity, self.shards.len(), idx);
+            shard.lock().set_capacity(shard_capacity);
+        }
+    }
+
+    /// Returns the size of all the key-value pairs in the cache, as measured by the `Meter` used
+    /// by the cache.
+    pub fn size(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.lock().size()).sum()
+    }
+
+    /// Removes all key-value pairs from the cache.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().clear();
+        }
+    }
+}
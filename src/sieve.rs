@@ -0,0 +1,313 @@
+mod concurrent;
+
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashbrown::HashTable;
+
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+pub use self::concurrent::ConcurrentSieve;
+
+/// An item, linked to the items inserted right before and after it.
+struct Node<K, V> {
+    key: K,
+    value: V,
+    visited: AtomicBool,
+    newer: Option<usize>,
+    older: Option<usize>,
+}
+
+/// A SIEVE cache, see <https://www.usenix.org/conference/nsdi24/presentation/zhang-yazhuo>.
+///
+/// Items are kept in insertion order, and a hit only sets their visited bit. The hand moves from
+/// the oldest item to the newest one, clearing the visited bits, and evicts the first item it
+/// finds unvisited. Unlike CLOCK, the items it passes stay where they are, so the new items that
+/// aren't hit again are evicted quickly. Hits, inserts and removals are O(1), and evictions
+/// amortized O(1).
+///
+/// A hit only sets the atomic visited bit, so it doesn't need exclusive access.
+pub struct Sieve<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// Indexes of the items in `nodes`, by the hash of their key.
+    table: HashTable<usize>,
+    nodes: Vec<Node<K, V>>,
+    /// The newest item.
+    head: Option<usize>,
+    /// The oldest item.
+    tail: Option<usize>,
+    /// The next item the hand looks at, the oldest one if `None`.
+    hand: Option<usize>,
+    hash_builder: S,
+    current_measure: M::Measure,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> Sieve<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> Sieve<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Sieve<K, V, S, M> {
+    /// Returns a reference to the value of the given key and records the hit.
+    ///
+    /// Only the atomic visited bit of the item is set, so this doesn't need exclusive access.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = &self.nodes[self.find(k)?];
+        node.visited.store(true, SeqCst);
+        Some(&node.value)
+    }
+
+    fn find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.table
+            .find(hash, |&i| self.nodes[i].key.borrow() == k)
+            .copied()
+    }
+
+    /// Returns the index of the item the next eviction picks, without clearing any visited bit.
+    fn victim(&self) -> Option<usize> {
+        let start = self.hand.or(self.tail)?;
+        let mut i = start;
+        while self.nodes[i].visited.load(SeqCst) {
+            i = self.nodes[i].newer.or(self.tail)?;
+            if i == start {
+                // Every item was visited, the hand clears them all and comes back.
+                break;
+            }
+        }
+        Some(i)
+    }
+
+    /// Inserts a new item as the newest one.
+    fn insert_new(&mut self, k: K, v: V) {
+        let hash = self.hash_builder.hash_one(&k);
+        let i = self.nodes.len();
+        self.nodes.push(Node {
+            key: k,
+            value: v,
+            visited: AtomicBool::new(false),
+            newer: None,
+            older: self.head,
+        });
+        match self.head.replace(i) {
+            Some(head) => self.nodes[head].newer = Some(i),
+            None => self.tail = Some(i),
+        }
+        let (nodes, hash_builder) = (&self.nodes, &self.hash_builder);
+        self.table
+            .insert_unique(hash, i, |&j| hash_builder.hash_one(&nodes[j].key));
+    }
+
+    /// Removes the item at `i` and returns it, the hand on it moves to the newer item.
+    fn remove_at(&mut self, i: usize) -> (K, V) {
+        let Node { newer, older, .. } = self.nodes[i];
+        match newer {
+            Some(newer) => self.nodes[newer].older = older,
+            None => self.head = older,
+        }
+        match older {
+            Some(older) => self.nodes[older].newer = newer,
+            None => self.tail = newer,
+        }
+        if self.hand == Some(i) {
+            self.hand = newer;
+        }
+        let hash = self.hash_builder.hash_one(&self.nodes[i].key);
+        if let Ok(entry) = self.table.find_entry(hash, |&j| j == i) {
+            entry.remove();
+        }
+        let node = self.nodes.swap_remove(i);
+        if i < self.nodes.len() {
+            // The last item was moved to `i`, fix the links to it.
+            let moved = self.nodes.len();
+            let Node { newer, older, .. } = self.nodes[i];
+            match newer {
+                Some(newer) => self.nodes[newer].older = Some(i),
+                None => self.head = Some(i),
+            }
+            match older {
+                Some(older) => self.nodes[older].newer = Some(i),
+                None => self.tail = Some(i),
+            }
+            if self.hand == Some(moved) {
+                self.hand = Some(i);
+            }
+            let hash = self.hash_builder.hash_one(&self.nodes[i].key);
+            if let Some(index) = self.table.find_mut(hash, |&j| j == moved) {
+                *index = i;
+            }
+        }
+        self.current_measure = self.meter.sub(
+            self.current_measure,
+            self.meter.measure(&node.key, &node.value),
+        );
+        (node.key, node.value)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for Sieve<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            table: HashTable::new(),
+            nodes: Vec::new(),
+            head: None,
+            tail: None,
+            hand: None,
+            hash_builder,
+            current_measure: Default::default(),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Sieve::get(self, k)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).map(|i| &self.nodes[i].value)
+    }
+
+    /// The first unvisited item from the hand, O(n) in the worst case.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.victim()
+            .map(|i| (&self.nodes[i].key, &self.nodes[i].value))
+    }
+
+    /// Updating an existing key keeps its position and counts as a hit.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        let new_size = self.meter.size(new_measure).unwrap_or(1);
+        if new_size > self.max_capacity {
+            // It could never fit, so the hand doesn't clear the visited bits for it. The value it
+            // replaces is dropped.
+            return self.pop(&k);
+        }
+        if let Some(i) = self.find(&k) {
+            self.nodes[i].visited.store(true, SeqCst);
+            let old = std::mem::replace(&mut self.nodes[i].value, v);
+            self.current_measure = self.meter.add(self.current_measure, new_measure);
+            self.current_measure = self
+                .meter
+                .sub(self.current_measure, self.meter.measure(&k, &old));
+            while self.size() > self.capacity() {
+                self.pop_by_policy();
+            }
+            return Some(old);
+        }
+        // Make room first, the new item would be the only unvisited one if all the others were.
+        while self.size() + new_size > self.capacity() {
+            if self.pop_by_policy().is_none() {
+                break;
+            }
+        }
+        self.current_measure = self.meter.add(self.current_measure, new_measure);
+        self.insert_new(k, v);
+        None
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = self.find(k)?;
+        Some(self.remove_at(i).1)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        let mut i = self.hand.or(self.tail)?;
+        while self.nodes[i].visited.swap(false, SeqCst) {
+            i = self.nodes[i].newer.or(self.tail)?;
+        }
+        self.hand = self.nodes[i].newer;
+        Some(self.remove_at(i))
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(k).is_some()
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+        self.max_capacity = capacity;
+    }
+
+    fn size(&self) -> u64 {
+        self.meter
+            .size(self.current_measure)
+            .unwrap_or(self.nodes.len() as u64)
+    }
+
+    fn clear(&mut self) {
+        self.table.clear();
+        self.nodes.clear();
+        self.head = None;
+        self.tail = None;
+        self.hand = None;
+        self.current_measure = Default::default();
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for Sieve<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
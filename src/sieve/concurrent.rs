@@ -0,0 +1,122 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use parking_lot::RwLock;
+
+use super::Sieve;
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+
+/// A thread-safe SIEVE cache with a shared read path.
+///
+/// A hit only sets the atomic visited bit of the item, so `get` runs under a shared read lock and
+/// readers never block each other. Only inserts, removals and the evictions they trigger take the
+/// exclusive write lock.
+pub struct ConcurrentSieve<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    inner: RwLock<Sieve<K, V, S, M>>,
+}
+
+impl<K: Hash + Eq, V> ConcurrentSieve<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Self::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> ConcurrentSieve<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Self::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, M: CountableMeter<K, V>> ConcurrentSieve<K, V, S, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter` with the
+    /// given hash builder.
+    pub fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            inner: RwLock::new(Sieve::with_meter_and_hasher(capacity, meter, hash_builder)),
+        }
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, and records the hit.
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.inner.read().get(k).cloned()
+    }
+
+    /// Returns a clone of the value corresponding to the given key, if any, without recording the
+    /// hit.
+    pub fn peek<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.inner.read().peek(k).cloned()
+    }
+
+    /// Inserts a key-value pair into the cache. If the key already existed, the old value is
+    /// returned.
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        self.inner.write().put(k, v)
+    }
+
+    /// Removes the given key from the cache and returns its corresponding value.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.write().pop(k)
+    }
+
+    /// Checks if the cache contains the given key.
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.read().contains(k)
+    }
+
+    /// Returns the number of key-value pairs in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.read().len()
+    }
+
+    /// Returns `true` if the cache contains no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().is_empty()
+    }
+
+    /// Returns the maximum size of the key-value pairs the cache can hold, as measured by the
+    /// `Meter` used by the cache.
+    pub fn capacity(&self) -> u64 {
+        self.inner.read().capacity()
+    }
+
+    /// Sets the size of the key-value pairs the cache can hold, as measured by the `Meter` used by
+    /// the cache.
+    pub fn set_capacity(&self, capacity: u64) {
+        self.inner.write().set_capacity(capacity);
+    }
+
+    /// Returns the size of all the key-value pairs in the cache, as measured by the `Meter` used
+    /// by the cache.
+    pub fn size(&self) -> u64 {
+        self.inner.read().size()
+    }
+
+    /// Removes all key-value pairs from the cache.
+    pub fn clear(&self) {
+        self.inner.write().clear();
+    }
+}
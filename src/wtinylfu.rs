@@ -0,0 +1,353 @@
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashlink::LinkedHashMap;
+
+use crate::admission::Admission;
+use crate::admission::TinyLfu;
+use crate::admission::MAX_SKETCH_CAPACITY;
+use crate::cache::Cache;
+use crate::meter::count_meter::Count;
+use crate::meter::count_meter::CountableMeter;
+use crate::BasicCache;
+
+/// A W-TinyLFU cache, see <https://arxiv.org/abs/1512.00727>.
+///
+/// New items enter a small LRU `window`, 1% of the capacity. The items it evicts are candidates
+/// for the main segmented LRU: while it's full, a `TinyLfu` filter admits a candidate only if it
+/// was accessed more often than the next victim of the main cache, so a burst of new keys can't
+/// flush it. Main items enter the `probation` segment, and move to the `protected` one, 80% of
+/// the main capacity, on a hit.
+///
+/// The capacity is measured by the `Meter`. The frequency sketch is sized for as many items as
+/// the capacity, up to a million.
+pub struct WTinyLfu<K, V, S = DefaultHashBuilder, M: CountableMeter<K, V> = Count> {
+    /// The least recently used item is at the front.
+    window: LinkedHashMap<K, V, S>,
+    /// The least recently used item is at the front.
+    probation: LinkedHashMap<K, V, S>,
+    /// The least recently used item is at the front.
+    protected: LinkedHashMap<K, V, S>,
+    window_measure: M::Measure,
+    probation_measure: M::Measure,
+    protected_measure: M::Measure,
+    sketch: TinyLfu,
+    max_capacity: u64,
+    meter: M,
+}
+
+impl<K: Hash + Eq, V> WTinyLfu<K, V> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: u64) -> Self {
+        Cache::with_meter_and_hasher(capacity, Count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, M: CountableMeter<K, V>> WTinyLfu<K, V, DefaultHashBuilder, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter(capacity: u64, meter: M) -> Self {
+        Cache::with_meter_and_hasher(capacity, meter, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, M: CountableMeter<K, V>> WTinyLfu<K, V, S, M> {
+    /// Returns the frequency filter admitting the items into the main cache.
+    pub fn sketch(&self) -> &TinyLfu {
+        &self.sketch
+    }
+
+    /// Returns the estimated number of recent accesses to the given key, cached or not.
+    pub fn frequency<Q>(&self, k: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sketch.estimate(self.hash_of(k))
+    }
+
+    /// Checks if the given key is in the main cache, past the window.
+    pub fn in_main<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.probation.contains_key(k) || self.protected.contains_key(k)
+    }
+
+    /// Returns the size of `measure`, or `count` if the meter doesn't measure anything.
+    fn size_of(&self, measure: M::Measure, count: usize) -> u64 {
+        self.meter.size(measure).unwrap_or(count as u64)
+    }
+
+    /// The window may hold 1% of the capacity.
+    fn window_capacity(&self) -> u64 {
+        Ord::max(self.max_capacity / 100, 1)
+    }
+
+    /// The protected segment may hold 80% of the main cache.
+    fn protected_capacity(&self) -> u64 {
+        self.max_capacity.saturating_sub(self.window_capacity()) / 5 * 4
+    }
+
+    fn main_size(&self) -> u64 {
+        let measure = self
+            .meter
+            .add(self.probation_measure, self.protected_measure);
+        self.size_of(measure, self.probation.len() + self.protected.len())
+    }
+
+    fn hash_of<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+    {
+        self.window.hasher().hash_one(key)
+    }
+
+    /// Returns the next victim of the main cache.
+    fn main_victim(&self) -> Option<(&K, &V)> {
+        self.probation.front().or_else(|| self.protected.front())
+    }
+
+    /// Records a hit on a cached key: a probation item is promoted to the protected segment,
+    /// the others move to the back of their segment.
+    fn touch<Q>(&mut self, k: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.window.to_back(k).is_some() || self.protected.to_back(k).is_some() {
+            return;
+        }
+        let Some((key, value)) = self.probation.remove_entry(k) else {
+            return;
+        };
+        let measure = self.meter.measure(k, &value);
+        self.probation_measure = self.meter.sub(self.probation_measure, measure);
+        self.protected_measure = self.meter.add(self.protected_measure, measure);
+        self.protected.insert(key, value);
+        self.demote();
+    }
+
+    /// Moves the least recently used protected items back to probation while the protected
+    /// segment is over its capacity.
+    fn demote(&mut self) {
+        while self.size_of(self.protected_measure, self.protected.len()) > self.protected_capacity()
+            && self.protected.len() > 1
+        {
+            let Some((key, value)) = self.protected.pop_front() else {
+                break;
+            };
+            let measure = self.meter.measure(&key, &value);
+            self.protected_measure = self.meter.sub(self.protected_measure, measure);
+            self.probation_measure = self.meter.add(self.probation_measure, measure);
+            self.probation.insert(key, value);
+        }
+    }
+
+    /// Moves the items the window evicts to the main cache, if the filter admits them.
+    fn drain_window(&mut self) {
+        while self.size_of(self.window_measure, self.window.len()) > self.window_capacity() {
+            let Some((key, value)) = self.window.pop_front() else {
+                break;
+            };
+            let measure = self.meter.measure(&key, &value);
+            self.window_measure = self.meter.sub(self.window_measure, measure);
+            let size = self.size_of(measure, 1);
+            let main_capacity = self.max_capacity.saturating_sub(self.window_capacity());
+            if self.main_size() + size > main_capacity {
+                if let Some((victim, _)) = self.main_victim() {
+                    let victim = self.hash_of(victim);
+                    if !self.sketch.admit(self.hash_of(&key), victim) {
+                        continue;
+                    }
+                }
+            }
+            self.probation_measure = self.meter.add(self.probation_measure, measure);
+            self.probation.insert(key, value);
+        }
+    }
+
+    /// Replaces the value of the given key in the segment it's in, or gives the value back if
+    /// the key isn't cached.
+    fn replace(&mut self, k: &K, v: V, new_measure: M::Measure) -> Result<V, V> {
+        let (value, measure) = if let Some(value) = self.window.get_mut(k) {
+            (value, &mut self.window_measure)
+        } else if let Some(value) = self.probation.get_mut(k) {
+            (value, &mut self.probation_measure)
+        } else if let Some(value) = self.protected.get_mut(k) {
+            (value, &mut self.protected_measure)
+        } else {
+            return Err(v);
+        };
+        let old = std::mem::replace(value, v);
+        *measure = self.meter.add(*measure, new_measure);
+        *measure = self.meter.sub(*measure, self.meter.measure(k, &old));
+        Ok(old)
+    }
+
+    /// Removes the item of the given key from the segment it's in.
+    fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, measure) = if let Some(value) = self.window.remove(k) {
+            (value, &mut self.window_measure)
+        } else if let Some(value) = self.probation.remove(k) {
+            (value, &mut self.probation_measure)
+        } else {
+            (self.protected.remove(k)?, &mut self.protected_measure)
+        };
+        *measure = self.meter.sub(*measure, self.meter.measure(k, &value));
+        Some(value)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, M: CountableMeter<K, V>> Cache<K, V, S, M>
+    for WTinyLfu<K, V, S, M>
+{
+    fn with_meter_and_hasher(capacity: u64, meter: M, hash_builder: S) -> Self {
+        Self {
+            window: LinkedHashMap::with_hasher(hash_builder.clone()),
+            probation: LinkedHashMap::with_hasher(hash_builder.clone()),
+            protected: LinkedHashMap::with_hasher(hash_builder),
+            window_measure: Default::default(),
+            probation_measure: Default::default(),
+            protected_measure: Default::default(),
+            sketch: TinyLfu::new(Ord::min(capacity, MAX_SKETCH_CAPACITY) as usize),
+            max_capacity: capacity,
+            meter,
+        }
+    }
+
+    fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sketch.record(self.hash_of(k));
+        self.touch(k);
+        self.peek(k)
+    }
+
+    fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.window
+            .get(k)
+            .or_else(|| self.probation.get(k))
+            .or_else(|| self.protected.get(k))
+    }
+
+    /// The next victim of the main cache, or of the window if the main cache is empty.
+    fn peek_by_policy(&self) -> Option<(&K, &V)> {
+        self.main_victim().or_else(|| self.window.front())
+    }
+
+    /// Updating an existing key counts as a hit.
+    fn put(&mut self, k: K, v: V) -> Option<V> {
+        let new_measure = self.meter.measure(&k, &v);
+        if self.size_of(new_measure, 1) > self.max_capacity {
+            // It could never fit, so no segment is flushed for it and the access isn't recorded.
+            // The value it replaces is dropped.
+            return self.pop(&k);
+        }
+        self.sketch.record(self.hash_of(&k));
+
+        self.touch(&k);
+        let old = match self.replace(&k, v, new_measure) {
+            Ok(old) => Some(old),
+            Err(v) => {
+                self.window_measure = self.meter.add(self.window_measure, new_measure);
+                self.window.insert(k, v);
+                self.drain_window();
+                None
+            }
+        };
+        while self.size() > self.capacity() {
+            self.pop_by_policy();
+        }
+        old
+    }
+
+    fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove(k)
+    }
+
+    fn pop_by_policy(&mut self) -> Option<(K, V)> {
+        let (key, value, measure) = if let Some((key, value)) = self.probation.pop_front() {
+            (key, value, &mut self.probation_measure)
+        } else if let Some((key, value)) = self.protected.pop_front() {
+            (key, value, &mut self.protected_measure)
+        } else {
+            let (key, value) = self.window.pop_front()?;
+            (key, value, &mut self.window_measure)
+        };
+        *measure = self.meter.sub(*measure, self.meter.measure(&key, &value));
+        Some((key, value))
+    }
+
+    fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.window.contains_key(k) || self.in_main(k)
+    }
+
+    fn len(&self) -> usize {
+        self.window.len() + self.probation.len() + self.protected.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.window.is_empty() && self.probation.is_empty() && self.protected.is_empty()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    fn set_capacity(&mut self, capacity: u64) {
+        self.max_capacity = capacity;
+        self.drain_window();
+        while self.size() > capacity {
+            self.pop_by_policy();
+        }
+    }
+
+    fn size(&self) -> u64 {
+        let measure = self
+            .meter
+            .add(self.probation_measure, self.protected_measure);
+        let measure = self.meter.add(self.window_measure, measure);
+        self.size_of(measure, self.len())
+    }
+
+    /// The accesses recorded by the sketch are kept.
+    fn clear(&mut self) {
+        self.window.clear();
+        self.probation.clear();
+        self.protected.clear();
+        self.window_measure = Default::default();
+        self.probation_measure = Default::default();
+        self.protected_measure = Default::default();
+    }
+}
+
+impl<K: Hash + Eq, V> BasicCache<K, V> for WTinyLfu<K, V> {
+    fn get_basic(&mut self, key: &K) -> Option<&V> {
+        Cache::get(self, key)
+    }
+
+    fn put_basic(&mut self, key: K, value: V) {
+        Cache::put(self, key, value);
+    }
+}
@@ -12,5 +12,54 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod arc;
+mod clock;
 mod lru;
 mod fifo;
+mod lfu;
+mod s3fifo;
+mod sharded;
+mod sieve;
+mod wtinylfu;
+mod admission;
+
+use common_cache::BytesMeter;
+use common_cache::Cache;
+use common_cache::DefaultHashBuilder;
+
+/// Checks how a policy holding 10 bytes measured by `BytesMeter` accounts for updates, removals
+/// and items too large to ever fit. Which items it evicts is left to the tests of each policy.
+pub fn check_bytes_meter<C>(mut cache: C)
+where
+    C: Cache<u64, Vec<u8>, DefaultHashBuilder, BytesMeter>,
+{
+    assert_eq!(cache.capacity(), 10);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    assert_eq!(cache.size(), 8);
+    assert_eq!(cache.put(2, vec![0u8; 2]), Some(vec![0u8; 4]));
+    assert_eq!(cache.size(), 6);
+
+    // A new item too large to ever fit isn't cached, and nothing is evicted for it.
+    assert_eq!(cache.put(3, vec![0u8; 11]), None);
+    assert!(!cache.contains(&3));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.size(), 6);
+    // Replacing an item with one too large to ever fit drops the old item.
+    assert_eq!(cache.put(2, vec![0u8; 11]), Some(vec![0u8; 2]));
+    assert!(!cache.contains(&2));
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.size(), 4);
+
+    assert_eq!(cache.pop(&1), Some(vec![0u8; 4]));
+    assert_eq!(cache.size(), 0);
+    cache.put(4, vec![0u8; 4]);
+    cache.put(5, vec![0u8; 4]);
+    cache.set_capacity(6);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.size(), 4);
+    cache.clear();
+    assert!(cache.is_empty());
+    assert_eq!(cache.size(), 0);
+    assert_eq!(cache.pop_by_policy(), None);
+}
@@ -0,0 +1,86 @@
+use common_cache::admission::Admission;
+use common_cache::admission::Admitted;
+use common_cache::admission::TinyLfu;
+use common_cache::fifo::Fifo;
+use common_cache::Cache;
+use common_cache::Count;
+use common_cache::DefaultHashBuilder;
+
+#[test]
+fn test_tiny_lfu()
+{
+    let mut filter = TinyLfu::new(16);
+    for _ in 0..5 {
+        filter.record(1);
+    }
+    filter.record(2);
+    assert_eq!(filter.estimate(1), 5);
+    assert_eq!(filter.estimate(2), 1);
+    assert!(filter.admit(1, 2));
+    assert!(!filter.admit(2, 1));
+    assert!(!filter.admit(2, 2));
+
+    // The counters are halved after 10 records per item of the capacity.
+    for _ in 6..160 {
+        filter.record(3);
+    }
+    assert_eq!(filter.estimate(1), 2);
+    assert_eq!(filter.estimate(2), 0);
+    filter.clear();
+    assert_eq!(filter.estimate(1), 0);
+}
+
+#[test]
+fn test_admitted_fifo()
+{
+    let mut cache = Admitted::new(Fifo::new(2), Count, TinyLfu::new(16));
+    cache.put(1, 10);
+    cache.put(2, 20);
+    // Seen as often as `1`, `3` isn't worth evicting it.
+    assert_eq!(cache.put(3, 30), None);
+    assert!(!cache.contains(&3));
+    assert_eq!(cache.len(), 2);
+
+    assert_eq!(cache.get(&3), None);
+    cache.put(3, 30);
+    assert!(cache.contains(&3));
+    assert!(!cache.contains(&1));
+    // An update of a cached key is always let in.
+    assert_eq!(cache.put(2, 21), Some(20));
+    assert_eq!(cache.inner().peek_by_policy(), Some((&2, &21)));
+}
+
+/// A filter keeping the cached items, sized like `with_meter_and_hasher` asked.
+struct KeepCached
+{
+    capacity: usize,
+}
+
+impl Admission for KeepCached
+{
+    fn with_capacity(capacity: usize) -> Self
+    {
+        Self { capacity }
+    }
+
+    fn record(&mut self, _hash: u64) {}
+
+    fn admit(&self, _candidate: u64, _victim: u64) -> bool
+    {
+        false
+    }
+}
+
+#[test]
+fn test_admitted_custom_filter()
+{
+    let mut cache: Admitted<Fifo<u64, u64>, DefaultHashBuilder, Count, KeepCached> =
+        Cache::with_meter_and_hasher(2, Count, DefaultHashBuilder::default());
+    assert_eq!(cache.admission().capacity, 2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.put(3, 30), None);
+    assert_eq!(cache.get(&3), None);
+    assert!(cache.contains(&1));
+    assert_eq!(cache.put(1, 11), Some(10));
+}
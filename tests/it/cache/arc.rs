@@ -0,0 +1,88 @@
+use common_cache::arc::ArcCache;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = ArcCache::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.put(1, 11), Some(10));
+    assert_eq!(cache.pop(&1), Some(11));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_scan_resistance()
+{
+    let mut cache = ArcCache::new(100);
+    for i in 0..100 {
+        cache.put(i, i);
+    }
+    for i in 0..50 {
+        cache.get(&i);
+    }
+    // A scan of new keys only flushes the keys seen once.
+    for i in 100..300 {
+        cache.put(i, i);
+    }
+    for i in 0..50 {
+        assert_eq!(cache.peek(&i), Some(&i));
+    }
+    assert_eq!(cache.len(), 100);
+}
+
+#[test]
+fn test_adaptive_target()
+{
+    let mut cache = ArcCache::new(4);
+    for i in 1..=4 {
+        cache.put(i, i);
+    }
+    cache.get(&1);
+    cache.get(&2);
+    assert_eq!(cache.peek_by_policy(), Some((&3, &3)));
+    cache.put(5, 5);
+    assert!(!cache.contains(&3));
+
+    // `3` was evicted from the recent items too soon, they get more room.
+    cache.put(3, 3);
+    assert_eq!(cache.target(), 1);
+    assert!(cache.contains(&3));
+    assert!(!cache.contains(&4));
+
+    // The recent items are at their target, the frequent ones are evicted.
+    cache.put(6, 6);
+    assert!(!cache.contains(&1));
+    // `1` was evicted from the frequent items too soon, they get the room back.
+    cache.put(1, 1);
+    assert_eq!(cache.target(), 0);
+    assert!(cache.contains(&1));
+    assert!(!cache.contains(&5));
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(ArcCache::with_meter(10, BytesMeter));
+
+    let mut cache = ArcCache::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.get(&1);
+    cache.put(2, vec![0u8; 2]);
+    // Both were hit and are in `t2`, `1` is the least recently used one.
+    cache.put(3, vec![0u8; 6]);
+    assert!(!cache.contains(&1));
+    assert!(cache.contains(&3));
+    assert_eq!(cache.size(), 8);
+    cache.set_capacity(6);
+    assert!(cache.size() <= 6);
+    assert!(cache.target() <= 6);
+}
@@ -0,0 +1,141 @@
+use common_cache::clock::Clock;
+use common_cache::clock::ClockPro;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = Clock::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.put(1, 11), Some(10));
+    assert_eq!(cache.pop(&1), Some(11));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_second_chance()
+{
+    let mut cache = Clock::new(3);
+    cache.put(1, 1);
+    cache.put(2, 2);
+    cache.put(3, 3);
+    cache.get(&1);
+    assert_eq!(cache.peek_by_policy(), Some((&2, &2)));
+    // `1` was hit, the hand passes it and evicts `2`.
+    cache.put(4, 4);
+    assert!(!cache.contains(&2));
+    assert_eq!(cache.peek_by_policy(), Some((&3, &3)));
+    cache.put(5, 5);
+    assert!(!cache.contains(&3));
+
+    // An update counts as a hit.
+    cache.put(4, 40);
+    cache.put(6, 6);
+    assert!(!cache.contains(&1));
+    assert_eq!(cache.peek(&4), Some(&40));
+    // Every item was hit, the hand clears them all and evicts the one it started from.
+    cache.get(&4);
+    cache.get(&5);
+    cache.get(&6);
+    assert_eq!(cache.pop_by_policy(), Some((5, 5)));
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(Clock::with_meter(10, BytesMeter));
+
+    let mut cache = Clock::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.get(&1);
+    cache.put(3, vec![0u8; 4]);
+    assert!(!cache.contains(&2));
+    // The hand moved `1` behind `3`, which goes first.
+    cache.put(4, vec![0u8; 4]);
+    assert!(!cache.contains(&3));
+    assert!(cache.contains(&1));
+    assert_eq!(cache.size(), 8);
+}
+
+#[test]
+fn test_pro_pub_and_get()
+{
+    let mut cache = ClockPro::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.put(1, 11), Some(10));
+    assert_eq!(cache.pop(&1), Some(11));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_pro_test_period()
+{
+    let mut cache = ClockPro::new(2);
+    cache.put(1, 1);
+    cache.put(2, 2);
+    cache.put(3, 3);
+    assert!(!cache.contains(&1));
+    assert_eq!(cache.test_len(), 1);
+    assert_eq!(cache.get(&1), None);
+
+    // Inserted again during its test period, `1` is hot.
+    cache.put(1, 1);
+    assert!(cache.is_hot(&1));
+    assert!(!cache.contains(&2));
+    assert!(cache.contains(&3));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_pro_scan_resistance()
+{
+    let mut cache = ClockPro::new(100);
+    for i in 0..50 {
+        cache.put(i, i);
+        cache.get(&i);
+    }
+    // Scans of new keys between the hits only flush the keys seen once.
+    for round in 1..4 {
+        for i in 0..100 {
+            cache.put(round * 1000 + i, i);
+        }
+        for i in 0..50 {
+            assert_eq!(cache.get(&i), Some(&i));
+        }
+    }
+    assert!((0..50).all(|i| cache.is_hot(&i)));
+    assert_eq!(cache.len(), 100);
+    assert!(cache.test_len() <= 100);
+}
+
+#[test]
+fn test_pro_bytes_meter()
+{
+    check_bytes_meter(ClockPro::with_meter(10, BytesMeter));
+
+    let mut cache = ClockPro::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.put(3, vec![0u8; 6]);
+    assert!(cache.contains(&3));
+    assert_eq!(cache.len(), 2);
+    // The cold page evicted for `3` stays in its test period.
+    assert_eq!(cache.test_len(), 1);
+    cache.set_capacity(6);
+    assert!(cache.size() <= 6);
+    assert!(cache.cold_target() <= 6);
+    cache.clear();
+    assert_eq!(cache.test_len(), 0);
+}
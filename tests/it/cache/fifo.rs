@@ -1,4 +1,8 @@
 use common_cache::fifo::Fifo;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
 
 #[test]
 fn test_pub_and_get()
@@ -10,4 +14,38 @@ fn test_pub_and_get()
     assert_eq!(cache.get(&2), Some(&20));
     assert_eq!(cache.len(), 2);
     assert_eq!(cache.len(), 2);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_evict_in_insertion_order()
+{
+    let mut cache = Fifo::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    // A hit doesn't save the oldest item.
+    assert_eq!(cache.get(&1), Some(&10));
+    // Updating a key keeps its position.
+    assert_eq!(cache.put(2, 21), Some(20));
+    cache.put(3, 30);
+    assert!(!cache.contains(&1));
+    assert_eq!(cache.peek_by_policy(), Some((&2, &21)));
+    assert_eq!(cache.pop_by_policy(), Some((2, 21)));
+    assert_eq!(cache.pop(&3), Some(30));
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(Fifo::with_meter(10, BytesMeter));
+
+    let mut cache = Fifo::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 2]);
+    // The update left `2` behind `1`, so `1` is evicted even though `2` shrank.
+    cache.put(3, vec![0u8; 6]);
+    assert!(!cache.contains(&1));
+    assert!(cache.contains(&2));
+    assert_eq!(cache.size(), 8);
+}
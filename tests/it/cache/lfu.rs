@@ -0,0 +1,160 @@
+use common_cache::lfu::Lfu;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = Lfu::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.frequency(&1), Some(2));
+}
+
+#[test]
+fn test_evict_least_frequently_used()
+{
+    let mut cache = Lfu::new(3);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    cache.put(3, 30);
+    cache.get(&1);
+    cache.get(&1);
+    cache.get(&3);
+    // `2` was never hit.
+    assert_eq!(cache.peek_by_policy(), Some((&2, &20)));
+    cache.put(4, 40);
+    assert!(!cache.contains(&2));
+    // `4` and `3` were accessed as often, `4` more recently.
+    cache.get(&4);
+    assert_eq!(cache.pop_by_policy(), Some((3, 30)));
+    // Updating a key counts as a hit.
+    assert_eq!(cache.put(4, 41), Some(40));
+    assert_eq!(cache.frequency(&4), Some(3));
+    assert_eq!(cache.pop_by_policy(), Some((1, 10)));
+    assert_eq!(cache.pop(&4), Some(41));
+    assert!(cache.is_empty());
+    assert_eq!(cache.peek_by_policy(), None);
+}
+
+#[test]
+fn test_aging()
+{
+    let mut cache = Lfu::new(3);
+    cache.set_aging_period(Some(4));
+    cache.put(1, 10);
+    cache.put(2, 20);
+    for _ in 0..3 {
+        cache.get(&1);
+    }
+    // The fourth hit halves every frequency.
+    cache.get(&2);
+    assert_eq!(cache.frequency(&1), Some(2));
+    assert_eq!(cache.frequency(&2), Some(1));
+    cache.put(3, 30);
+    cache.put(4, 40);
+    assert!(!cache.contains(&2));
+    assert!(cache.contains(&3));
+
+    // Without aging, an item that was hot long ago is never evicted.
+    cache.set_aging_period(None);
+    for _ in 0..10 {
+        cache.get(&1);
+    }
+    for i in 5..100 {
+        cache.put(i, i * 10);
+        cache.get(&i);
+    }
+    assert!(cache.contains(&1));
+
+    // Frequencies 1, 2 and 3 all age to 1, the items hit less stay first in line.
+    cache.clear();
+    cache.put(1, 10);
+    cache.put(2, 20);
+    cache.put(3, 30);
+    cache.get(&2);
+    cache.get(&3);
+    cache.set_aging_period(Some(1));
+    cache.get(&3);
+    assert_eq!(cache.frequency(&3), Some(1));
+    assert_eq!(cache.pop_by_policy(), Some((1, 10)));
+    assert_eq!(cache.pop_by_policy(), Some((2, 20)));
+    assert_eq!(cache.pop_by_policy(), Some((3, 30)));
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(Lfu::with_meter(10, BytesMeter));
+
+    let mut cache = Lfu::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.get(&1);
+    cache.put(2, vec![0u8; 2]);
+    // `2` was updated once, `1` read once, `2` is more recent.
+    cache.put(3, vec![0u8; 6]);
+    assert!(!cache.contains(&1));
+    assert_eq!(cache.size(), 8);
+    // `3` was never hit, it goes first.
+    cache.set_capacity(6);
+    assert_eq!(cache.size(), 2);
+    assert!(cache.contains(&2));
+}
+
+/// Compares the cache with a scan for the least frequently, then least recently, used item.
+#[test]
+fn test_against_scan()
+{
+    // key -> (value, frequency, time of the last access)
+    let mut expected = std::collections::HashMap::new();
+    let mut cache = Lfu::new(50);
+    for time in 0..20_000_u64 {
+        let key = rand::random::<u8>() % 100;
+        let victim = expected
+            .iter()
+            .min_by_key(|(_, &(_, freq, last))| (freq, last))
+            .map(|(&key, &(value, ..))| (key, value));
+        assert_eq!(cache.peek_by_policy().map(|(&k, &v)| (k, v)), victim);
+        match rand::random::<u8>() % 4 {
+            0 => {
+                let value = cache.get(&key).copied();
+                if let Some((_, freq, last)) = expected.get_mut(&key) {
+                    *freq += 1;
+                    *last = time;
+                }
+                assert_eq!(value, expected.get(&key).map(|&(value, ..)| value));
+            }
+            1 => {
+                assert_eq!(cache.pop(&key), expected.remove(&key).map(|(value, ..)| value));
+            }
+            _ => {
+                let old = cache.put(key, time);
+                let freq = match expected.get(&key) {
+                    Some(&(value, freq, _)) => {
+                        assert_eq!(old, Some(value));
+                        freq + 1
+                    }
+                    None => {
+                        assert_eq!(old, None);
+                        if expected.len() == 50 {
+                            expected.remove(&victim.map(|(key, _)| key).unwrap());
+                        }
+                        1
+                    }
+                };
+                expected.insert(key, (time, freq, time));
+            }
+        }
+        assert_eq!(cache.len(), expected.len());
+    }
+    while let Some((key, _)) = cache.pop_by_policy() {
+        assert!(expected.remove(&key).is_some());
+    }
+    assert!(expected.is_empty());
+}
@@ -1,5 +1,10 @@
+use common_cache::BytesMeter;
+use common_cache::Cache;
+use common_cache::DefaultHashBuilder;
 use hashlink::LruCache;
 
+use super::check_bytes_meter;
+
 #[test]
 fn test_pub_and_get()
 {
@@ -10,4 +15,40 @@ fn test_pub_and_get()
     assert_eq!(cache.get(&2), Some(&20));
     assert_eq!(cache.len(), 2);
     assert_eq!(cache.len(), 2);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_evict_least_recently_used()
+{
+    let mut cache = common_cache::LruCache::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    cache.put(3, 30);
+    assert!(!cache.contains(&2));
+    assert_eq!(cache.peek_by_policy(), Some((&1, &10)));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_meter()
+{
+    let mut cache: common_cache::LruCache<u64, Vec<u8>, DefaultHashBuilder, BytesMeter> =
+        Cache::with_meter_and_hasher(10, BytesMeter, DefaultHashBuilder::default());
+    cache.put(1, vec![0; 4]);
+    cache.put(2, vec![0; 4]);
+    assert_eq!(cache.size(), 8);
+    cache.put(3, vec![0; 4]);
+    assert!(!cache.contains(&1));
+    assert_eq!(cache.size(), 8);
+    cache.set_capacity(4);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.pop(&3), Some(vec![0; 4]));
+    assert_eq!(cache.size(), 0);
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(common_cache::LruCache::with_meter(10, BytesMeter));
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::thread;
+
+use common_cache::s3fifo::ConcurrentS3Fifo;
+use common_cache::s3fifo::S3Fifo;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = S3Fifo::with_capacity(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_one_hit_wonders_are_evicted_first()
+{
+    let mut cache = S3Fifo::with_capacity(100);
+    for i in 0..100 {
+        cache.put(i, i);
+    }
+    for i in 0..10 {
+        cache.get(&i);
+        cache.get(&i);
+    }
+    // A scan of new keys only flushes the keys that were never hit.
+    for i in 100..200 {
+        cache.put(i, i);
+    }
+    for i in 0..10 {
+        assert_eq!(cache.peek(&i), Some(&i));
+    }
+    assert_eq!(cache.len(), 100);
+}
+
+#[test]
+fn test_ghost_hit()
+{
+    let mut cache = S3Fifo::with_capacity(10);
+    for i in 0..11 {
+        cache.put(i, i);
+    }
+    assert!(!cache.contains(&0));
+    // `0` is remembered by the ghost queue, it survives a scan this time.
+    cache.put(0, 0);
+    cache.get(&0);
+    for i in 11..30 {
+        cache.put(i, i);
+    }
+    assert!(cache.contains(&0));
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(S3Fifo::with_meter(10, BytesMeter));
+}
+
+#[test]
+fn test_many_entries()
+{
+    let mut cache = S3Fifo::with_capacity(10_000);
+    for i in 0..100_000 {
+        cache.put(i, i);
+        assert_eq!(cache.get(&i), Some(&i));
+    }
+    assert_eq!(cache.len(), 10_000);
+    assert_eq!(cache.size(), 10_000);
+}
+
+#[test]
+fn test_insert_and_remove()
+{
+    let mut cache = S3Fifo::with_capacity(10);
+    assert_eq!(cache.insert(1, 10), None);
+    assert_eq!(cache.insert(1, 11), Some(10));
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.remove(&1), Some(11));
+    assert_eq!(cache.remove(&1), None);
+    assert!(cache.is_empty());
+
+    // Ghost hits never push the cache over its capacity.
+    for round in 0..3 {
+        for i in 0..30 {
+            cache.insert(i, round);
+            assert!(cache.len() <= 10);
+        }
+    }
+}
+
+#[test]
+fn test_concurrent_readers()
+{
+    let cache = Arc::new(ConcurrentS3Fifo::with_capacity(1000));
+    for i in 0..1000_u64 {
+        cache.insert(i, i);
+    }
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..100_000_u64 {
+                    let key = i % 100;
+                    assert_eq!(cache.get(&key), Some(key));
+                }
+            })
+        })
+        .collect();
+    for handle in readers {
+        handle.join().unwrap();
+    }
+
+    // The keys hit by the readers survive a scan.
+    for i in 1000..2000_u64 {
+        cache.insert(i, i);
+    }
+    for i in 0..100_u64 {
+        assert!(cache.contains(&i));
+    }
+    assert_eq!(cache.len(), 1000);
+}
+
+#[test]
+fn test_concurrent_writers()
+{
+    let cache = Arc::new(ConcurrentS3Fifo::with_meter(100, BytesMeter));
+    let tasks: Vec<_> = (0..4_u64)
+        .map(|task| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..10_000_u64 {
+                    let key = (i * 4 + task) % 500;
+                    match i % 3 {
+                        0 => {
+                            cache.insert(key, vec![0u8; (key % 10 + 1) as usize]);
+                        }
+                        1 => {
+                            cache.get(&key);
+                        }
+                        _ => {
+                            cache.remove(&key);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in tasks {
+        handle.join().unwrap();
+    }
+
+    // The items readers see are the ones the queues account for.
+    let values: Vec<_> = (0..500_u64).filter_map(|key| cache.peek(&key)).collect();
+    assert_eq!(cache.len(), values.len());
+    assert_eq!(cache.size(), values.iter().map(|v| v.len() as u64).sum());
+    assert!(cache.size() <= 100);
+    cache.clear();
+    assert!(cache.is_empty());
+    assert_eq!(cache.size(), 0);
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::thread;
+
+use common_cache::fifo::Fifo;
+use common_cache::s3fifo::S3Fifo;
+use common_cache::BytesMeter;
+use common_cache::DefaultHashBuilder;
+use common_cache::LruCache;
+use common_cache::ShardedCache;
+
+#[test]
+fn test_pub_and_get()
+{
+    let cache: ShardedCache<i32, i32, LruCache<i32, i32>> = ShardedCache::new(4, 10);
+    assert_eq!(cache.shards(), 4);
+    assert_eq!(cache.capacity(), 10);
+    cache.insert(1, 10);
+    cache.insert(2, 20);
+    assert_eq!(cache.get(&1), Some(10));
+    assert_eq!(cache.insert(2, 21), Some(20));
+    assert_eq!(cache.remove(&2), Some(21));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_multi_thread()
+{
+    let cache: Arc<ShardedCache<u64, u64, S3Fifo<u64, u64>>> =
+        Arc::new(ShardedCache::new(8, 1000));
+    let handles: Vec<_> = (0..4_u64)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..10_000_u64 {
+                    let key = t * 10_000 + i;
+                    cache.insert(key, key);
+                    // Other threads may have evicted it already.
+                    if let Some(value) = cache.get(&key) {
+                        assert_eq!(value, key);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(cache.len(), 1000);
+}
+
+type BytesFifo = Fifo<u64, Vec<u8>, DefaultHashBuilder, BytesMeter>;
+
+#[test]
+fn test_meter()
+{
+    let cache: ShardedCache<u64, Vec<u8>, BytesFifo, _, _> =
+        ShardedCache::with_meter_and_hasher(4, 1024, BytesMeter, DefaultHashBuilder::default());
+    for i in 0..100 {
+        cache.insert(i, vec![0; 64]);
+    }
+    assert!(cache.size() <= 1024);
+    cache.set_capacity(512);
+    assert!(cache.size() <= 512);
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_small_capacity()
+{
+    // Fewer items than shards, every shard must still hold one.
+    let cache: ShardedCache<i32, i32, Fifo<i32, i32>> = ShardedCache::new(8, 3);
+    assert_eq!(cache.shards(), 3);
+    assert_eq!(cache.capacity(), 3);
+    for i in 0..100 {
+        cache.insert(i, i);
+        assert_eq!(cache.get(&i), Some(i));
+    }
+    assert_eq!(cache.len(), 3);
+
+    cache.set_capacity(1);
+    assert_eq!(cache.capacity(), 3);
+    cache.insert(100, 100);
+    assert_eq!(cache.get(&100), Some(100));
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::thread;
+
+use common_cache::sieve::ConcurrentSieve;
+use common_cache::sieve::Sieve;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = Sieve::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.put(1, 11), Some(10));
+    assert_eq!(cache.pop(&1), Some(11));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_sieve_order()
+{
+    let mut cache = Sieve::new(3);
+    cache.put(1, 1);
+    cache.put(2, 2);
+    cache.put(3, 3);
+    cache.get(&1);
+    assert_eq!(cache.peek_by_policy(), Some((&2, &2)));
+    // The hand clears `1` and evicts `2`.
+    cache.put(4, 4);
+    assert!(!cache.contains(&2));
+    // `1` stays in place, the hand evicts the newer items it finds unvisited first.
+    cache.put(5, 5);
+    assert!(!cache.contains(&3));
+    cache.put(6, 6);
+    assert!(!cache.contains(&4));
+    cache.put(7, 7);
+    assert!(!cache.contains(&5));
+    assert!(cache.contains(&1));
+
+    // Every item was visited, the hand clears them all and evicts the one it started from.
+    cache.get(&1);
+    cache.get(&6);
+    cache.put(7, 70);
+    assert_eq!(cache.pop_by_policy(), Some((6, 6)));
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(Sieve::with_meter(10, BytesMeter));
+
+    let mut cache = Sieve::with_meter(10, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    cache.get(&1);
+    cache.put(3, vec![0u8; 4]);
+    assert!(!cache.contains(&2));
+    // Unlike CLOCK, the hand left `1` where it was with its bit cleared, so it goes before `3`.
+    cache.put(4, vec![0u8; 4]);
+    assert!(!cache.contains(&1));
+    assert!(cache.contains(&3));
+    assert_eq!(cache.size(), 8);
+}
+
+#[test]
+fn test_concurrent_readers()
+{
+    let cache = Arc::new(ConcurrentSieve::new(1000));
+    for i in 0..1000_u64 {
+        cache.insert(i, i);
+    }
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..100_000_u64 {
+                    let key = i % 100;
+                    assert_eq!(cache.get(&key), Some(key));
+                }
+            })
+        })
+        .collect();
+    for handle in readers {
+        handle.join().unwrap();
+    }
+
+    // The keys hit by the readers survive a scan.
+    for i in 1000..2000_u64 {
+        cache.insert(i, i);
+    }
+    for i in 0..100_u64 {
+        assert!(cache.contains(&i));
+    }
+    assert_eq!(cache.len(), 1000);
+}
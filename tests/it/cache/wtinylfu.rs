@@ -0,0 +1,81 @@
+use common_cache::wtinylfu::WTinyLfu;
+use common_cache::BytesMeter;
+use common_cache::Cache;
+
+use super::check_bytes_meter;
+
+#[test]
+fn test_pub_and_get()
+{
+    let mut cache = WTinyLfu::new(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.put(1, 11), Some(10));
+    assert_eq!(cache.pop(&1), Some(11));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_admit_frequent()
+{
+    let mut cache = WTinyLfu::new(2);
+    cache.put(1, 1);
+    cache.put(2, 2);
+    assert!(cache.in_main(&1));
+    // `2` leaves the window, seen as often as `1` it isn't worth evicting it.
+    cache.put(3, 3);
+    assert!(!cache.contains(&2));
+    assert!(cache.contains(&1));
+
+    cache.get(&2);
+    cache.put(2, 2);
+    assert_eq!(cache.frequency(&2), 3);
+    assert!(!cache.contains(&3));
+    // Seen more often than `1`, `2` takes its place in the main cache.
+    cache.put(4, 4);
+    assert!(cache.in_main(&2));
+    assert!(!cache.contains(&1));
+    assert!(cache.contains(&4));
+}
+
+#[test]
+fn test_scan_resistance()
+{
+    let mut cache = WTinyLfu::new(100);
+    for i in 0..100 {
+        cache.put(i, i);
+    }
+    for _ in 0..3 {
+        for i in 0..50 {
+            cache.get(&i);
+        }
+    }
+    // A scan of new keys doesn't flush the frequent keys.
+    for i in 100..300 {
+        cache.put(i, i);
+    }
+    for i in 0..50 {
+        assert_eq!(cache.peek(&i), Some(&i));
+    }
+    assert!(cache.frequency(&0) > cache.frequency(&150));
+    assert_eq!(cache.len(), 100);
+}
+
+#[test]
+fn test_bytes_meter()
+{
+    check_bytes_meter(WTinyLfu::with_meter(10, BytesMeter));
+
+    // The window holds 1% of the bytes, 10 here.
+    let mut cache = WTinyLfu::with_meter(1000, BytesMeter);
+    cache.put(1, vec![0u8; 4]);
+    cache.put(2, vec![0u8; 4]);
+    assert!(!cache.in_main(&1));
+    cache.put(3, vec![0u8; 4]);
+    assert!(cache.in_main(&1));
+    assert!(!cache.in_main(&2));
+    assert_eq!(cache.size(), 12);
+}